@@ -0,0 +1,125 @@
+use rocket::serde::json;
+use std::collections::BTreeMap;
+
+/// Anchor texts of every link, keyed by its target and then its source, so the texts a page
+/// gives are replaced whenever the page is stored again or removed.
+static ANCHOR_TEXTS_TREE: &str = "anchor_texts";
+/// Targets of the links recorded for every source.
+static ANCHOR_SOURCES_TREE: &str = "anchor_sources";
+/// Anchor texts kept before they were keyed by source, which cannot be retracted.
+static LEGACY_ANCHORS_TREE: &str = "anchors";
+static MAX_ANCHOR_TEXTS: usize = 32;
+
+/// Normalizes the text of a link, collapsing its whitespace.
+pub fn clean_anchor_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn get_anchor_key(target: u128, source: u128) -> String {
+    format!("{}\0{}", target, source)
+}
+
+fn get_sources_targets(sources: &sled::Tree, source: u128) -> sled::Result<Vec<u128>> {
+    Ok(match sources.get(source.to_string())? {
+        Some(value) => json::from_str::<Vec<String>>(String::from_utf8_lossy(&value).as_ref())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|target| target.parse().ok())
+            .collect(),
+        None => vec![],
+    })
+}
+
+/// Anchor texts other pages use when linking to the document `id`, the first
+/// `MAX_ANCHOR_TEXTS` distinct ones.
+pub fn get_anchor_texts(db: &sled::Db, id: u128) -> sled::Result<Vec<String>> {
+    let anchors = db.open_tree(ANCHOR_TEXTS_TREE)?;
+    let mut texts: Vec<String> = vec![];
+    for anchor in anchors.scan_prefix(format!("{}\0", id)) {
+        let (_, value) = anchor?;
+        for text in json::from_str::<Vec<String>>(String::from_utf8_lossy(&value).as_ref())
+            .unwrap_or_default()
+        {
+            if texts.len() >= MAX_ANCHOR_TEXTS {
+                return Ok(texts);
+            }
+            if !texts.iter().any(|known| known.eq_ignore_ascii_case(&text)) {
+                texts.push(text);
+            }
+        }
+    }
+    Ok(texts)
+}
+
+/// Replaces the anchor texts the document `source` gives to the pages it links to with those of
+/// `links`, given as target and text. Returns the targets whose anchor texts changed.
+pub fn set_anchor_texts(
+    db: &sled::Db,
+    source: u128,
+    links: &[(u128, String)],
+) -> sled::Result<Vec<u128>> {
+    let anchors = db.open_tree(ANCHOR_TEXTS_TREE)?;
+    let sources = db.open_tree(ANCHOR_SOURCES_TREE)?;
+
+    let mut texts: BTreeMap<u128, Vec<String>> = BTreeMap::new();
+    for (target, text) in links.iter() {
+        let text = clean_anchor_text(text);
+        if *target == source || text.is_empty() {
+            continue;
+        }
+        let target_texts = texts.entry(*target).or_default();
+        if !target_texts
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(&text))
+        {
+            target_texts.push(text);
+        }
+    }
+
+    let mut changed = vec![];
+    for target in get_sources_targets(&sources, source)? {
+        if !texts.contains_key(&target) && anchors.remove(get_anchor_key(target, source))?.is_some()
+        {
+            changed.push(target);
+        }
+    }
+    for (target, target_texts) in texts.iter() {
+        let value = json::to_string(target_texts).unwrap();
+        let previous = anchors.insert(get_anchor_key(*target, source), value.as_str())?;
+        if previous.as_deref() != Some(value.as_bytes()) {
+            changed.push(*target);
+        }
+    }
+
+    if texts.is_empty() {
+        sources.remove(source.to_string())?;
+    } else {
+        let targets: Vec<String> = texts.keys().map(|target| target.to_string()).collect();
+        sources.insert(
+            source.to_string(),
+            json::to_string(&targets).unwrap().as_str(),
+        )?;
+    }
+    Ok(changed)
+}
+
+/// Rebuilds the anchor texts kept before they were keyed by source from the links of the
+/// stored entries, given as source, target and text, and drops the old tree.
+pub fn migrate_legacy_anchors<I>(db: &sled::Db, links: I) -> sled::Result<()>
+where
+    I: Iterator<Item = (u128, Vec<(u128, String)>)>,
+{
+    if !db
+        .tree_names()
+        .iter()
+        .any(|name| name.as_ref() == LEGACY_ANCHORS_TREE.as_bytes())
+    {
+        return Ok(());
+    }
+
+    for (source, source_links) in links {
+        set_anchor_texts(db, source, &source_links)?;
+    }
+    db.drop_tree(LEGACY_ANCHORS_TREE)?;
+    Ok(())
+}
@@ -1,186 +1,1247 @@
-use anyhow::Result;
-use finalfusion::{compat::text::ReadText, embeddings::Embeddings};
-use futures::StreamExt;
+use clap::Parser;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
 use lingua::Language::{English, Spanish};
 use lingua::{LanguageDetector, LanguageDetectorBuilder};
-use reqwest::Url;
+use reqwest::header::{
+    HeaderName, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION,
+};
+use reqwest::redirect::Policy;
+use reqwest::{StatusCode, Url};
 use rocket::serde::json;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::env::var;
-use std::{fs::File, io::BufReader};
-use tree::{get_sentence_embedding, CrawledEntry};
-use uuid::Uuid;
-use voyager::{
-    scraper::Selector,
-    {Collector, Crawler, CrawlerConfig, Response, Scraper},
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+use tree::anchors::{clean_anchor_text, get_anchor_texts};
+use tree::crawl::{CrawlConfig, CrawlState, QueuedUrl, UrlFilter};
+use tree::dbpedia::{load_dbpedia_from_env, Dbpedia};
+use tree::robots::Robots;
+use tree::sitemap::{parse_sitemap, read_sitemap, Sitemap, SitemapEntry, MAX_SITEMAP_LENGTH};
+use tree::weighting::{load_weighting_from_env, Weighting};
+use tree::{
+    get_entry, get_entry_embedding, get_entry_id, get_passage_embeddings, load_embedder,
+    load_embedder_from_env, now, store_entry, update_entry, CrawledEntry, Embedder,
+    EmbeddingsFormat, Link,
 };
+use voyager::scraper::{ElementRef, Html, Selector};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    struct Explorer {
-        /// visited urls mapped with all the urls that link to that url
-        visited: HashMap<Url, HashSet<Url>>,
-        link_selector: Selector,
-        title_selector: Selector,
-        header_selector: Selector,
-        meta_title_selector: Selector,
-        meta_site_name_selector: Selector,
-        meta_description_selector: Selector,
-    }
-    impl Default for Explorer {
-        fn default() -> Self {
-            Self {
-                visited: Default::default(),
-                link_selector: Selector::parse("a").unwrap(),
-                title_selector: Selector::parse("title").unwrap(),
-                header_selector: Selector::parse("h1").unwrap(),
-                meta_title_selector: Selector::parse("meta[property=\"title\"], meta[property=\"og:title\"]").unwrap(),
-                meta_site_name_selector: Selector::parse("meta[property=\"site_name\"], meta[property=\"og:site_name\"]").unwrap(),
-                meta_description_selector: Selector::parse("meta[property=\"description\"], meta[name=\"description\"], meta[property=\"og:description\"]").unwrap(),
-            }
-        }
-    }
-
-    impl Scraper for Explorer {
-        type Output = (Url, String, String, String, usize);
-        type State = Url;
-
-        fn scrape(
-            &mut self,
-            mut response: Response<Self::State>,
-            crawler: &mut Crawler<Self>,
-        ) -> Result<Option<Self::Output>> {
-            if let Some(origin) = response.state.take() {
-                self.visited
-                    .entry(response.response_url.clone())
-                    .or_default()
-                    .insert(origin);
-            }
-
-            for link in response.html().select(&self.link_selector) {
-                if let Some(href) = link.value().attr("href") {
-                    if let Ok(url) = response.response_url.join(href) {
-                        crawler.visit_with_state(url, response.response_url.clone());
+/// Elements whose text makes up the readable body of a page.
+static BLOCK_ELEMENTS: [&str; 11] = [
+    "p",
+    "li",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "pre",
+    "td",
+    "dd",
+];
+
+/// Elements that hold navigation, boilerplate or code rather than content.
+static BOILERPLATE_ELEMENTS: [&str; 9] = [
+    "nav", "header", "footer", "aside", "script", "style", "noscript", "form", "template",
+];
+
+/// Paths sitemaps are looked for at on hosts whose robots.txt lists none.
+static WELL_KNOWN_SITEMAPS: [&str; 2] = ["/sitemap.xml", "/sitemap_index.xml"];
+
+/// Priority the sitemap protocol gives urls listed without one.
+static DEFAULT_PRIORITY: f32 = 0.5;
+
+/// Contents of a crawled page.
+struct Page {
+    title: String,
+    header: String,
+    description: String,
+    body: String,
+    links: Vec<Link>,
+}
+
+struct Explorer {
+    link_selector: Selector,
+    title_selector: Selector,
+    header_selector: Selector,
+    meta_title_selector: Selector,
+    meta_site_name_selector: Selector,
+    meta_description_selector: Selector,
+    main_selector: Selector,
+    block_selector: Selector,
+}
+
+impl Default for Explorer {
+    fn default() -> Self {
+        Self {
+            link_selector: Selector::parse("a").unwrap(),
+            title_selector: Selector::parse("title").unwrap(),
+            header_selector: Selector::parse("h1").unwrap(),
+            meta_title_selector: Selector::parse("meta[property=\"title\"], meta[property=\"og:title\"]").unwrap(),
+            meta_site_name_selector: Selector::parse("meta[property=\"site_name\"], meta[property=\"og:site_name\"]").unwrap(),
+            meta_description_selector: Selector::parse("meta[property=\"description\"], meta[name=\"description\"], meta[property=\"og:description\"]").unwrap(),
+            main_selector: Selector::parse("main, article, [role=\"main\"]").unwrap(),
+            block_selector: Selector::parse(&BLOCK_ELEMENTS.join(", ")).unwrap(),
+        }
+    }
+}
+
+impl Explorer {
+    /// Readable text of the page, taken from the blocks of its main content and skipping
+    /// anything nested in navigation, boilerplate or script elements.
+    fn extract_body(&self, html: &Html) -> String {
+        let blocks: Vec<ElementRef> = match html.select(&self.main_selector).next() {
+            Some(main) => main.select(&self.block_selector).collect(),
+            None => html.select(&self.block_selector).collect(),
+        };
+
+        blocks
+            .into_iter()
+            .filter(|block| {
+                !block
+                    .ancestors()
+                    .any(|ancestor| match ancestor.value().as_element() {
+                        Some(element) => BOILERPLATE_ELEMENTS
+                            .iter()
+                            .chain(BLOCK_ELEMENTS.iter())
+                            .any(|name| *name == element.name()),
+                        None => false,
+                    })
+            })
+            .map(|block| block.text().collect::<Vec<_>>().join(" "))
+            .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn scrape(&self, url: &Url, html: &Html) -> Page {
+        let mut links: Vec<Link> = Vec::new();
+        for link in html.select(&self.link_selector) {
+            if let Some(href) = link.value().attr("href") {
+                if let Ok(mut url) = url.join(href) {
+                    url.set_fragment(None);
+                    let link = Link {
+                        url: url.into(),
+                        text: clean_anchor_text(&link.text().collect::<Vec<_>>().join(" ")),
+                    };
+                    if !links
+                        .iter()
+                        .any(|known| known.url == link.url && known.text == link.text)
+                    {
+                        links.push(link);
                     }
                 }
             }
+        }
 
-            let mut title = String::from("");
-            match response.html().select(&self.meta_site_name_selector).next() {
-                Some(value) => {
-                    if let Some(value) = value.value().attr("content") {
+        let mut title = String::from("");
+        match html.select(&self.meta_site_name_selector).next() {
+            Some(value) => {
+                if let Some(value) = value.value().attr("content") {
+                    title = value.trim().to_owned();
+                }
+            }
+            None => {
+                if let Some(value) = html.select(&self.title_selector).next() {
+                    if let Some(value) = value.text().next() {
                         title = value.trim().to_owned();
                     }
                 }
-                None => {
-                    if let Some(value) = response.html().select(&self.title_selector).next() {
-                        if let Some(value) = value.text().next() {
-                            title = value.trim().to_owned();
-                        }
+            }
+        }
+
+        let mut header = String::from("");
+        match html.select(&self.meta_title_selector).next() {
+            Some(value) => {
+                if let Some(value) = value.value().attr("content") {
+                    header = value.trim().to_owned();
+                }
+            }
+            None => {
+                if let Some(value) = html.select(&self.header_selector).next() {
+                    if let Some(value) = value.text().next() {
+                        header = value.trim().to_owned();
                     }
                 }
             }
+        }
 
-            let mut header = String::from("");
-            match response.html().select(&self.meta_title_selector).next() {
-                Some(value) => {
-                    if let Some(value) = value.value().attr("content") {
-                        header = value.trim().to_owned();
+        let mut description = String::from("");
+        if let Some(value) = html.select(&self.meta_description_selector).next() {
+            if let Some(value) = value.value().attr("content") {
+                description = value.trim().to_owned();
+            }
+        }
+
+        Page {
+            title,
+            header,
+            description,
+            body: self.extract_body(html),
+            links,
+        }
+    }
+}
+
+/// Robots.txt of a host, fetched before any other request is made to it.
+enum RobotsStatus {
+    Unknown,
+    Fetching,
+    Known(Robots),
+}
+
+/// Politeness state and pending urls of a host, keyed by origin.
+struct Host {
+    robots: RobotsStatus,
+    /// Requests to the host in flight.
+    active: usize,
+    /// Earliest time the next request may start.
+    next_request: Instant,
+    /// Depth of the shallowest page of the host found, given to the pages its sitemaps list.
+    depth: usize,
+    /// Pages of the host requested so far.
+    fetched: usize,
+    sitemaps: VecDeque<Url>,
+    pages: Pages,
+}
+
+/// Pages of a host waiting to be fetched, with their depth.
+#[derive(Default)]
+struct Pages {
+    /// Pages sitemaps list, handed out before the pages found through links.
+    listed: BinaryHeap<ListedPage>,
+    linked: VecDeque<(Url, usize)>,
+}
+
+impl Pages {
+    fn is_empty(&self) -> bool {
+        self.listed.is_empty() && self.linked.is_empty()
+    }
+
+    fn pop(&mut self) -> Option<(Url, usize)> {
+        match self.listed.pop() {
+            Some(page) => Some((page.url, page.depth)),
+            None => self.linked.pop_front(),
+        }
+    }
+}
+
+/// Page a sitemap listed, ordered by its priority, then by how recently it was modified.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct ListedPage {
+    /// Sitemap priority, in thousandths.
+    priority: u32,
+    lastmod: u64,
+    url: Url,
+    depth: usize,
+}
+
+enum Request {
+    Robots(String, Url),
+    Sitemap(String, Url),
+    Page(String, Url, usize),
+}
+
+/// Outcome of fetching a page, conditionally if it was crawled before.
+enum PageFetch {
+    Modified {
+        html: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+    NotModified,
+    /// The page moved to the given url, which is crawled in its place.
+    Redirected(Url),
+    /// The page is missing or is not html, so it is not crawled.
+    Skipped,
+    /// The request failed, so the page stays queued for a later run.
+    Failed,
+}
+
+enum Fetched {
+    Robots(String, Robots),
+    Sitemap(String, Url, Sitemap),
+    Page(String, Url, usize, PageFetch),
+}
+
+/// Urls waiting to be crawled, handed out so that every host gets its robots.txt checked
+/// first, at most `max_per_host` requests at a time and a delay between them. Queued and
+/// visited page urls are kept in `state`, so a stopped crawl picks up where it was.
+struct Frontier {
+    hosts: HashMap<String, Host>,
+    state: CrawlState,
+    sitemaps_seen: HashSet<String>,
+    /// Hosts besides its own whose urls each sitemap may list, as their robots.txt references
+    /// it.
+    sitemap_hosts: HashMap<String, HashSet<String>>,
+    filter: UrlFilter,
+    max_depth: usize,
+    max_pages_per_host: Option<usize>,
+    max_per_host: usize,
+    /// Delay between requests to a host that does not ask for a longer one.
+    delay: Duration,
+}
+
+impl Frontier {
+    fn get_host(&mut self, origin: &str, depth: usize) -> &mut Host {
+        let host = self.hosts.entry(origin.to_string()).or_insert(Host {
+            robots: RobotsStatus::Unknown,
+            active: 0,
+            next_request: Instant::now(),
+            depth,
+            fetched: 0,
+            sitemaps: VecDeque::new(),
+            pages: Pages::default(),
+        });
+        host.depth = host.depth.min(depth);
+        host
+    }
+
+    /// Queues a seed, which is crawled even if it was indexed before this crawl started.
+    fn push_seed(&mut self, mut url: Url) {
+        url.set_fragment(None);
+        if !["http", "https"].contains(&url.scheme()) || !self.filter.is_allowed(&url) {
+            return;
+        }
+        let queued = QueuedUrl::default();
+        match self.state.queue_seed(url.as_str(), &queued) {
+            Ok(true) => self.push_queued(url, queued),
+            Ok(false) => (),
+            Err(e) => println!("Error: {:?}. Error queueing {}.", e, url),
+        }
+    }
+
+    fn push(&mut self, url: Url, depth: usize) {
+        self.queue(
+            url,
+            QueuedUrl {
+                depth,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Queues a page a sitemap listed, ahead of the pages found through links. Pages visited
+    /// before are queued again if the sitemap says they were modified since.
+    fn push_listed(&mut self, entry: SitemapEntry, depth: usize) {
+        if let Ok(url) = Url::parse(&entry.url) {
+            self.queue(
+                url,
+                QueuedUrl {
+                    depth,
+                    priority: Some(entry.priority.unwrap_or(DEFAULT_PRIORITY)),
+                    lastmod: entry.lastmod,
+                },
+            );
+        }
+    }
+
+    fn queue(&mut self, mut url: Url, queued: QueuedUrl) {
+        url.set_fragment(None);
+        if queued.depth > self.max_depth
+            || !["http", "https"].contains(&url.scheme())
+            || !self.filter.is_allowed(&url)
+        {
+            return;
+        }
+        match self.state.queue(url.as_str(), &queued) {
+            Ok(true) => self.push_queued(url, queued),
+            Ok(false) => (),
+            Err(e) => println!("Error: {:?}. Error queueing {}.", e, url),
+        }
+    }
+
+    /// Hands out `url`, already in the crawl state, to its host.
+    fn push_queued(&mut self, url: Url, queued: QueuedUrl) {
+        let origin = url.origin().ascii_serialization();
+        let host = self.get_host(&origin, queued.depth);
+        match queued.priority {
+            Some(priority) => host.pages.listed.push(ListedPage {
+                priority: (priority * 1000.0) as u32,
+                lastmod: queued.lastmod.unwrap_or(0),
+                url,
+                depth: queued.depth,
+            }),
+            None => host.pages.linked.push_back((url, queued.depth)),
+        }
+    }
+
+    /// Queues `url` again whether or not it was visited, to check a stored page for changes.
+    /// Its links are not followed.
+    fn push_recrawl(&mut self, url: Url) {
+        let queued = QueuedUrl {
+            depth: self.max_depth,
+            ..Default::default()
+        };
+        match self.state.requeue(url.as_str(), &queued) {
+            Ok(true) => self.push_queued(url, queued),
+            Ok(false) => (),
+            Err(e) => println!("Error: {:?}. Error queueing {}.", e, url),
+        }
+    }
+
+    /// Queues a sitemap of a host reached at `depth`, whose pages are queued one level deeper,
+    /// so hosts at the maximum depth are not expanded.
+    fn push_sitemap(&mut self, url: Url, depth: usize) {
+        if depth >= self.max_depth
+            || !["http", "https"].contains(&url.scheme())
+            || !self.filter.is_allowed(&url)
+            || !self.sitemaps_seen.insert(url.to_string())
+        {
+            return;
+        }
+        let origin = url.origin().ascii_serialization();
+        self.get_host(&origin, depth).sitemaps.push_back(url);
+    }
+
+    /// Whether `sitemap` may list `url`, that is whether `url` is on the host of the sitemap or
+    /// on a host whose robots.txt references it, as the sitemap protocol requires.
+    fn may_list(&self, sitemap: &Url, url: &Url) -> bool {
+        match url.host_str() {
+            Some(host) => {
+                sitemap.host_str() == Some(host)
+                    || self
+                        .sitemap_hosts
+                        .get(sitemap.as_str())
+                        .map_or(false, |hosts| hosts.contains(host))
+            }
+            None => false,
+        }
+    }
+
+    /// Queues the sitemaps or pages listed by the `sitemap` fetched from `url` of `origin`,
+    /// leaving out those on hosts it may not list.
+    fn push_sitemap_entries(&mut self, origin: &str, url: &Url, sitemap: Sitemap) {
+        let depth = self.hosts.get(origin).map_or(0, |host| host.depth);
+        let (entries, is_index) = match sitemap {
+            Sitemap::Index(entries) => (entries, true),
+            Sitemap::Urls(entries) => (entries, false),
+        };
+
+        let mut skipped = 0;
+        for entry in entries {
+            let listed = match Url::parse(&entry.url) {
+                Ok(listed) => listed,
+                Err(_) => continue,
+            };
+            if !self.may_list(url, &listed) {
+                skipped += 1;
+                continue;
+            }
+
+            if is_index {
+                // Sitemaps of an index may list the hosts the index may.
+                if let Some(hosts) = self.sitemap_hosts.get(url.as_str()).cloned() {
+                    self.sitemap_hosts
+                        .entry(listed.to_string())
+                        .or_default()
+                        .extend(hosts);
+                }
+                self.push_sitemap(listed, depth);
+            } else {
+                self.push_listed(entry, depth + 1);
+            }
+        }
+        if skipped > 0 {
+            println!(
+                "Skipped {} urls of sitemap {} on hosts it may not list.",
+                skipped, url
+            );
+        }
+    }
+
+    fn has_work(host: &Host) -> bool {
+        !host.sitemaps.is_empty() || !host.pages.is_empty()
+    }
+
+    /// Next request whose host is free to take it at `now`.
+    fn next_ready(&mut self, now: Instant) -> Option<Request> {
+        for (origin, host) in self.hosts.iter_mut() {
+            if !Self::has_work(host) || host.active >= self.max_per_host || host.next_request > now
+            {
+                continue;
+            }
+
+            let request = match &host.robots {
+                RobotsStatus::Fetching => continue,
+                RobotsStatus::Unknown => {
+                    match Url::parse(origin).and_then(|url| url.join("/robots.txt")) {
+                        Ok(url) => {
+                            host.robots = RobotsStatus::Fetching;
+                            Request::Robots(origin.clone(), url)
+                        }
+                        Err(_) => {
+                            host.robots = RobotsStatus::Known(Robots::disallow_all());
+                            continue;
+                        }
                     }
                 }
-                None => {
-                    if let Some(value) = response.html().select(&self.header_selector).next() {
-                        if let Some(value) = value.text().next() {
-                            header = value.trim().to_owned();
+                RobotsStatus::Known(robots) => match host.sitemaps.pop_front() {
+                    Some(url) => Request::Sitemap(origin.clone(), url),
+                    None if self
+                        .max_pages_per_host
+                        .map_or(false, |max_pages| host.fetched >= max_pages) =>
+                    {
+                        while let Some((url, _)) = host.pages.pop() {
+                            Self::remove(&self.state, &url);
+                        }
+                        continue;
+                    }
+                    None => {
+                        let mut allowed = None;
+                        while let Some((url, depth)) = host.pages.pop() {
+                            if robots.is_allowed(&url) {
+                                allowed = Some(Request::Page(origin.clone(), url, depth));
+                                break;
+                            }
+                            Self::remove(&self.state, &url);
+                        }
+                        match allowed {
+                            Some(request) => request,
+                            None => continue,
                         }
                     }
+                },
+            };
+
+            host.active += 1;
+            if let Request::Page(..) = request {
+                host.fetched += 1;
+            }
+            let delay = match &host.robots {
+                RobotsStatus::Known(Robots {
+                    crawl_delay: Some(crawl_delay),
+                    ..
+                }) => self.delay.max(*crawl_delay),
+                _ => self.delay,
+            };
+            host.next_request = now + delay;
+            return Some(request);
+        }
+        None
+    }
+
+    /// Earliest time a host with pending urls may take another request.
+    fn next_wakeup(&self) -> Option<Instant> {
+        self.hosts
+            .values()
+            .filter(|host| {
+                Self::has_work(host)
+                    && host.active < self.max_per_host
+                    && !matches!(host.robots, RobotsStatus::Fetching)
+            })
+            .map(|host| host.next_request)
+            .min()
+    }
+
+    /// Takes `url` out of the crawl state without visiting it.
+    fn remove(state: &CrawlState, url: &Url) {
+        if let Err(e) = state.remove(url.as_str()) {
+            println!("Error: {:?}. Error removing {} from the frontier.", e, url);
+        }
+    }
+
+    fn visit(&self, url: &Url) {
+        if let Err(e) = self.state.visit(url.as_str()) {
+            println!("Error: {:?}. Error recording the visit of {}.", e, url);
+        }
+    }
+
+    fn finish(&mut self, origin: &str) {
+        if let Some(host) = self.hosts.get_mut(origin) {
+            host.active = host.active.saturating_sub(1);
+        }
+    }
+
+    /// Records the robots.txt of `origin` and queues the sitemaps it lists, or the sitemaps at
+    /// well known paths if it lists none.
+    fn set_robots(&mut self, origin: &str, robots: Robots) {
+        let depth = match self.hosts.get(origin) {
+            Some(host) => host.depth,
+            None => return,
+        };
+        let sitemaps: Vec<Url> = if robots.sitemaps.is_empty() {
+            WELL_KNOWN_SITEMAPS
+                .iter()
+                .filter_map(|path| Url::parse(origin).and_then(|url| url.join(path)).ok())
+                .filter(|url| robots.is_allowed(url))
+                .collect()
+        } else {
+            robots
+                .sitemaps
+                .iter()
+                .filter_map(|sitemap| Url::parse(sitemap).ok())
+                .collect()
+        };
+        let host = Url::parse(origin)
+            .ok()
+            .and_then(|origin| origin.host_str().map(String::from));
+        for url in sitemaps {
+            if let Some(host) = &host {
+                if url.host_str() != Some(host.as_str()) {
+                    self.sitemap_hosts
+                        .entry(url.to_string())
+                        .or_default()
+                        .insert(host.clone());
                 }
             }
+            self.push_sitemap(url, depth);
+        }
+        if let Some(host) = self.hosts.get_mut(origin) {
+            host.robots = RobotsStatus::Known(robots);
+        }
+    }
+}
+
+/// Robots.txt at `url`. Hosts without one may be crawled freely, while hosts that fail to
+/// serve it are left alone.
+async fn fetch_robots(http_client: &reqwest::Client, url: Url, user_agent: &str) -> Robots {
+    match http_client.get(url.clone()).send().await {
+        Ok(response) if response.status().is_success() => match response.text().await {
+            Ok(text) => Robots::parse(&text, user_agent),
+            Err(_) => Robots::disallow_all(),
+        },
+        Ok(response) if response.status().is_client_error() => Robots::allow_all(),
+        Ok(_) => Robots::disallow_all(),
+        Err(e) => {
+            println!("Error: {:?}. Error fetching {}.", e, url);
+            Robots::disallow_all()
+        }
+    }
+}
+
+/// Sitemap or sitemap index at `url`, which may be gzipped. The body is read up to
+/// `MAX_SITEMAP_LENGTH` bytes as it arrives, so larger ones are cut short rather than held
+/// whole. Sitemaps that fail to load list no urls.
+async fn fetch_sitemap(http_client: &reqwest::Client, url: Url) -> Sitemap {
+    let mut response = match http_client.get(url.clone()).send().await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(_) => return Sitemap::Urls(vec![]),
+        Err(e) => {
+            println!("Error: {:?}. Error fetching sitemap {}.", e, url);
+            return Sitemap::Urls(vec![]);
+        }
+    };
+
+    let max_length = MAX_SITEMAP_LENGTH as usize;
+    let mut bytes = Vec::new();
+    while bytes.len() < max_length {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                let end = chunk.len().min(max_length - bytes.len());
+                bytes.extend_from_slice(&chunk[..end]);
+            }
+            Ok(None) => break,
+            Err(e) => {
+                println!("Error: {:?}. Error fetching sitemap {}.", e, url);
+                return Sitemap::Urls(vec![]);
+            }
+        }
+    }
 
-            let mut description = String::from("");
-            if let Some(value) = response
-                .html()
-                .select(&self.meta_description_selector)
-                .next()
+    match read_sitemap(&bytes) {
+        Ok(text) => parse_sitemap(&text),
+        Err(e) => {
+            println!("Error: {:?}. Error reading sitemap {}.", e, url);
+            Sitemap::Urls(vec![])
+        }
+    }
+}
+
+/// Fetches the page at `url`, asking for it only if it changed since the fetch that returned
+/// `etag` and `last_modified`. Redirects are not followed but handed back, so their targets go
+/// through the frontier. Pages that are missing or are not html are `PageFetch::Skipped`, while
+/// requests that failed, or that the server could not answer, are `PageFetch::Failed`.
+async fn fetch_page(
+    http_client: &reqwest::Client,
+    url: Url,
+    etag: Option<String>,
+    last_modified: Option<String>,
+) -> PageFetch {
+    let mut request = http_client.get(url.clone());
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = match request.send().await {
+        Ok(response) if response.status() == StatusCode::NOT_MODIFIED => {
+            return PageFetch::NotModified
+        }
+        Ok(response) if response.status().is_redirection() => {
+            return match response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok())
             {
-                if let Some(value) = value.value().attr("content") {
-                    description = value.trim().to_owned();
+                Some(target) => PageFetch::Redirected(target),
+                None => PageFetch::Skipped,
+            };
+        }
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) if response.status().is_server_error() => return PageFetch::Failed,
+        Ok(_) => return PageFetch::Skipped,
+        Err(e) => {
+            println!("Error: {:?}. Error fetching {}.", e, url);
+            return PageFetch::Failed;
+        }
+    };
+
+    let get_header = |name: HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).to_string())
+    };
+    let is_html = match get_header(CONTENT_TYPE) {
+        Some(content_type) => content_type.contains("html"),
+        None => true,
+    };
+    if !is_html {
+        return PageFetch::Skipped;
+    }
+
+    let etag = get_header(ETAG);
+    let last_modified = get_header(LAST_MODIFIED);
+    match response.text().await {
+        Ok(html) => PageFetch::Modified {
+            html,
+            etag,
+            last_modified,
+        },
+        Err(_) => PageFetch::Failed,
+    }
+}
+
+/// Makes `request`. Pages are fetched with `page_client`, which does not follow redirects, and
+/// conditionally on the `stored` entry of the page, if any.
+fn fetch(
+    http_client: &reqwest::Client,
+    page_client: &reqwest::Client,
+    request: Request,
+    user_agent: &str,
+    stored: Option<CrawledEntry>,
+) -> BoxFuture<'static, Fetched> {
+    let http_client = http_client.clone();
+    let page_client = page_client.clone();
+    let user_agent = user_agent.to_string();
+    async move {
+        match request {
+            Request::Robots(origin, url) => {
+                Fetched::Robots(origin, fetch_robots(&http_client, url, &user_agent).await)
+            }
+            Request::Sitemap(origin, url) => {
+                let sitemap = fetch_sitemap(&http_client, url.clone()).await;
+                Fetched::Sitemap(origin, url, sitemap)
+            }
+            Request::Page(origin, url, depth) => {
+                let (etag, last_modified) = match stored {
+                    Some(stored) => (stored.etag, stored.last_modified),
+                    None => (None, None),
+                };
+                let page = fetch_page(&page_client, url.clone(), etag, last_modified).await;
+                Fetched::Page(origin, url, depth, page)
+            }
+        }
+    }
+    .boxed()
+}
+
+/// Where crawled pages go: embedded and stored in the url database, or sent to the ingest
+/// endpoint of a running server, which embeds them itself with its own cached DBpedia lookups.
+/// Pages are recorded as visited in `state` once stored, so pages that failed to be are
+/// crawled again when the crawl resumes.
+struct Store {
+    state: CrawlState,
+    db: Option<sled::Db>,
+    ingest_address: Option<String>,
+    ingest_token: Option<String>,
+    http_client: reqwest::Client,
+    dbpedia: Dbpedia,
+    embeddings: Box<dyn Embedder>,
+    weighting: Weighting,
+}
+
+impl Store {
+    fn visit(&self, url: &str) {
+        if let Err(e) = self.state.visit(url) {
+            println!("Error: {:?}. Error recording the visit of {}.", e, url);
+        }
+    }
+
+    async fn store(&self, mut entry: CrawledEntry) {
+        match (&self.db, &self.ingest_address) {
+            (Some(db), _) => {
+                let vec = match get_entry_embedding(
+                    &self.dbpedia,
+                    self.embeddings.as_ref(),
+                    &self.weighting,
+                    &entry,
+                )
+                .await
+                {
+                    Some(vec) => vec,
+                    None => {
+                        println!("Skipped {}, as none of its words are embedded.", entry.url);
+                        self.visit(&entry.url);
+                        return;
+                    }
+                };
+                entry.vec = vec.to_vec();
+                entry.passages =
+                    get_passage_embeddings(self.embeddings.as_ref(), &self.weighting, &entry);
+
+                match store_entry(db, &entry) {
+                    Ok(_) => {
+                        print!("Crawled {}\n", entry.url);
+                        self.visit(&entry.url);
+                    }
+                    Err(e) => {
+                        println!("Error: {:?}. Error storing {}.", e, entry.url)
+                    }
+                }
+            }
+            (None, Some(address)) => {
+                let mut request = self.http_client.post(format!("{}/_ingest", address));
+                if let Some(token) = &self.ingest_token {
+                    request = request.header("X-Ingest-Token", token);
+                }
+                match request
+                    .json(&entry)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                {
+                    Ok(_) => {
+                        print!("Crawled {}\n", entry.url);
+                        self.visit(&entry.url);
+                    }
+                    // The server turned the page itself down, sending it again would not help.
+                    // Failed authentication, throttling and server errors leave it to be sent
+                    // again on the next run.
+                    Err(e) if is_rejected(&e) => {
+                        println!(
+                            "Skipped {}, rejected by the ingest endpoint: {:?}.",
+                            entry.url, e
+                        );
+                        self.visit(&entry.url);
+                    }
+                    Err(e) => {
+                        println!(
+                            "Error: {:?}. Error sending {} to ingest endpoint.",
+                            e, entry.url
+                        )
+                    }
                 }
             }
+            (None, None) => (),
+        }
+    }
+}
 
-            Ok(Some((
-                response.response_url,
-                title,
-                header,
-                description,
-                response.depth,
-            )))
+fn is_rejected(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => {
+            status.is_client_error()
+                && ![
+                    StatusCode::UNAUTHORIZED,
+                    StatusCode::FORBIDDEN,
+                    StatusCode::TOO_MANY_REQUESTS,
+                ]
+                .contains(&status)
         }
+        None => false,
     }
+}
+
+/// Crawls the web from a set of seed urls into the url database, or into a running server
+/// when `INGEST_ADDRESS` is set, authenticated with `INGEST_TOKEN`.
+#[derive(Parser)]
+struct Args {
+    /// TOML file with the crawl settings, see `CrawlConfig`. Options given here override it.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Urls to start crawling from.
+    seeds: Vec<String>,
+    /// File with more seed urls, one per line.
+    #[arg(long)]
+    seed_file: Option<PathBuf>,
+    /// Keeps the crawl to this domain and its subdomains. Can be repeated.
+    #[arg(long = "allow-domain")]
+    allow_domains: Vec<String>,
+    /// Never crawls this domain or its subdomains. Can be repeated.
+    #[arg(long = "deny-domain")]
+    deny_domains: Vec<String>,
+    /// Only crawls urls matching this regex, or one of the repeated ones.
+    #[arg(long)]
+    include: Vec<String>,
+    /// Never crawls urls matching this regex. Can be repeated.
+    #[arg(long)]
+    exclude: Vec<String>,
+    #[arg(long)]
+    max_depth: Option<usize>,
+    #[arg(long)]
+    max_pages_per_host: Option<usize>,
+    /// Url database to write to.
+    #[arg(long)]
+    db_path: Option<String>,
+    /// Database the progress of the crawl is kept in.
+    #[arg(long)]
+    state_path: Option<String>,
+    /// Crawls visited urls again once they are this many days old.
+    #[arg(long)]
+    recrawl_after_days: Option<u64>,
+    /// Forgets the progress of earlier runs and starts over from the seeds.
+    #[arg(long)]
+    fresh: bool,
+    /// Checks the stored pages that are due for changes instead of crawling from the seeds.
+    /// Changed pages are embedded and indexed again.
+    #[arg(long)]
+    recrawl: bool,
+    #[arg(long)]
+    embeddings_path: Option<PathBuf>,
+    #[arg(long)]
+    embeddings_format: Option<String>,
+}
 
-    let max_concurrent_requests = match var("MAX_CONCURRENT_REQUESTS") {
-        Ok(number) => number.parse().unwrap_or(100),
-        Err(_) => 100,
+/// Settings of the config file, or the defaults, overridden by the command line.
+fn get_crawl_config(args: &Args) -> Result<CrawlConfig, Box<dyn std::error::Error>> {
+    let mut config = match &args.config {
+        Some(path) => CrawlConfig::load(path)?,
+        None => CrawlConfig::default(),
     };
 
-    let config = CrawlerConfig::default()
-        .disallow_domains(vec!["facebook.com", "google.com"])
-        // stop after 3 jumps
-        .max_depth(4)
-        // maximum of requests that are active
-        .max_concurrent_requests(max_concurrent_requests);
-    // .respect_robots_txt();
+    // Seeds given on the command line replace the configured ones rather than adding to them.
+    if !args.seeds.is_empty() {
+        config.seeds = args.seeds.clone();
+    }
+    config.seed_file = args.seed_file.clone().or(config.seed_file);
+    config
+        .allow_domains
+        .extend(args.allow_domains.iter().cloned());
+    config
+        .deny_domains
+        .extend(args.deny_domains.iter().cloned());
+    config.include.extend(args.include.iter().cloned());
+    config.exclude.extend(args.exclude.iter().cloned());
+    config.max_depth = args.max_depth.unwrap_or(config.max_depth);
+    config.max_pages_per_host = args.max_pages_per_host.or(config.max_pages_per_host);
+    config.db_path = args.db_path.clone().unwrap_or(config.db_path);
+    config.state_path = args.state_path.clone().unwrap_or(config.state_path);
+    config.recrawl_after_days = args.recrawl_after_days.or(config.recrawl_after_days);
+    config.embeddings_path = args.embeddings_path.clone().or(config.embeddings_path);
+    config.embeddings_format = args.embeddings_format.clone().or(config.embeddings_format);
+    Ok(config)
+}
 
-    let mut collector = Collector::new(Explorer::default(), config);
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let config = get_crawl_config(&args)?;
 
-    match var("START_URL") {
-        Ok(url) => {
-            collector.crawler_mut().visit(url);
+    let embeddings = match &config.embeddings_path {
+        Some(path) => {
+            let format = match &config.embeddings_format {
+                Some(name) => match name.parse() {
+                    Ok(format) => format,
+                    Err(_) => return Err(format!("Unknown embeddings format {}", name).into()),
+                },
+                None => EmbeddingsFormat::Text,
+            };
+            load_embedder(path, format)
         }
-        Err(e) => {
-            println!("Error: {:?}. Set the START_URL environment variable to where you want to start crawling.", e);
-            return Err("Environment variable not set".into());
+        None => load_embedder_from_env(),
+    }
+    .unwrap();
+
+    // When a server is running it holds the database lock, so entries are sent to its ingest
+    // endpoint instead of being written to sled directly.
+    let ingest_address = var("INGEST_ADDRESS").ok();
+    let ingest_token = var("INGEST_TOKEN").ok().filter(|token| !token.is_empty());
+    let db = match ingest_address {
+        Some(_) => None,
+        None => Some(sled::open(&config.db_path).expect("open")),
+    };
+
+    let weighting = match &db {
+        Some(db) => load_weighting_from_env(db)?,
+        None => Weighting::mean(),
+    };
+
+    // A fresh crawl fetches pages again even if they were indexed by an earlier one.
+    let state = CrawlState::new(
+        &sled::open(&config.state_path).expect("open"),
+        if args.fresh { None } else { db.clone() },
+        config
+            .recrawl_after_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+    )?;
+    if args.fresh {
+        state.clear()?;
+    }
+    let queued = state.get_queued()?;
+
+    let mut frontier = Frontier {
+        hosts: HashMap::new(),
+        state,
+        sitemaps_seen: HashSet::new(),
+        sitemap_hosts: HashMap::new(),
+        filter: UrlFilter::new(&config)?,
+        max_depth: config.max_depth,
+        max_pages_per_host: config.max_pages_per_host,
+        max_per_host: config.max_concurrent_requests_per_host,
+        delay: Duration::from_millis(config.crawl_delay_ms),
+    };
+
+    if !queued.is_empty() {
+        println!("Resuming the crawl with {} queued urls.", queued.len());
+    }
+    for (url, queued) in queued {
+        if let Ok(url) = Url::parse(&url) {
+            frontier.push_queued(url, queued);
         }
     }
 
-    let mut p = project_root::get_project_root().unwrap();
-    p.push("glove.6B/glove.6B.50d.txt");
-    let mut reader = BufReader::new(File::open(p).unwrap());
+    if args.recrawl {
+        let db = match &db {
+            Some(db) => db,
+            None => {
+                println!("Error: recrawling reads the url database. Stop the server and unset INGEST_ADDRESS.");
+                return Err("Url database not available".into());
+            }
+        };
+
+        let now = now();
+        let mut due = 0;
+        for entry in db.iter() {
+            let (_, value) = entry?;
+            if let Ok(entry) =
+                json::from_str::<CrawledEntry>(String::from_utf8_lossy(&value).as_ref())
+            {
+                if let (true, Ok(url)) = (entry.is_due(now), Url::parse(&entry.url)) {
+                    frontier.push_recrawl(url);
+                    due += 1;
+                }
+            }
+        }
+        println!("Recrawling {} due pages.", due);
+    }
 
-    let embeddings = Embeddings::read_text(&mut reader).unwrap();
-    let db = sled::open("urlDatabase").expect("open");
+    let seeds = if args.recrawl {
+        vec![]
+    } else {
+        config.get_seeds()?
+    };
+    if seeds.is_empty() && frontier.hosts.is_empty() {
+        println!("Error: no seed urls. Pass them as arguments, list them in the seeds or seed_file settings, or set the START_URL environment variable.");
+        return Err("No seed urls".into());
+    }
+    for seed in seeds {
+        match Url::parse(&seed) {
+            Ok(url) => frontier.push_seed(url),
+            Err(e) => println!("Error: {:?}. Seed {} is not a valid url.", e, seed),
+        }
+    }
 
     let languages = vec![English, Spanish];
     let detector: LanguageDetector = LanguageDetectorBuilder::from_languages(&languages).build();
-    let http_client = reqwest::Client::new();
-
-    while let Some(output) = collector.next().await {
-        if let Ok((url, title, header, description, _)) = output {
-            let url_string: String = url.clone().into();
-            let uuid = Uuid::new_v5(&Uuid::NAMESPACE_URL, url_string.as_bytes());
-            let language = match detector.detect_language_of(&title) {
-                Some(language) => language.iso_code_639_1().to_string(),
-                None => String::from("unk"),
-            };
-            if let Some(vec) = get_sentence_embedding(&http_client, &embeddings, &title).await {
-                let crawled_json = CrawledEntry {
-                    url: url_string,
-                    title: title.clone(),
-                    header,
-                    description,
-                    vec: vec.to_vec(),
-                    language,
-                };
+    let http_client = reqwest::Client::builder()
+        .user_agent(&config.user_agent)
+        .build()?;
+    let page_client = reqwest::Client::builder()
+        .user_agent(&config.user_agent)
+        .redirect(Policy::none())
+        .build()?;
+    let explorer = Explorer::default();
+
+    // Pages are embedded and stored apart from the loop making requests, so slow knowledge base
+    // lookups do not hold up the crawl. The channel is bounded so fetching waits for storing
+    // when it runs ahead.
+    let store = Store {
+        state: frontier.state.clone(),
+        db: db.clone(),
+        ingest_address,
+        ingest_token,
+        http_client: http_client.clone(),
+        dbpedia: load_dbpedia_from_env(http_client.clone(), db.as_ref()),
+        embeddings,
+        weighting,
+    };
+    let (sender, mut receiver) = mpsc::channel::<CrawledEntry>(config.max_concurrent_embeddings);
+    let max_concurrent_embeddings = config.max_concurrent_embeddings;
+    let storing = tokio::spawn(async move {
+        futures::stream::poll_fn(|cx| receiver.poll_recv(cx))
+            .for_each_concurrent(max_concurrent_embeddings, |entry| store.store(entry))
+            .await
+    });
+
+    let mut in_flight = FuturesUnordered::new();
+    loop {
+        while in_flight.len() < config.max_concurrent_requests {
+            match frontier.next_ready(Instant::now()) {
+                Some(request) => {
+                    let stored = match (&request, &db) {
+                        (Request::Page(_, url, _), Some(db)) => {
+                            get_entry(db, get_entry_id(url.as_str())).ok().flatten()
+                        }
+                        _ => None,
+                    };
+                    in_flight.push(fetch(
+                        &http_client,
+                        &page_client,
+                        request,
+                        &config.user_agent,
+                        stored,
+                    ))
+                }
+                None => break,
+            }
+        }
+
+        let wakeup = if in_flight.len() < config.max_concurrent_requests {
+            frontier.next_wakeup()
+        } else {
+            None
+        };
+        let fetched = match (in_flight.is_empty(), wakeup) {
+            (true, None) => break,
+            (true, Some(wakeup)) => {
+                sleep_until(wakeup).await;
+                continue;
+            }
+            (false, None) => in_flight.next().await,
+            (false, Some(wakeup)) => tokio::select! {
+                fetched = in_flight.next() => fetched,
+                _ = sleep_until(wakeup) => continue,
+            },
+        };
+
+        let (url, depth, html, etag, last_modified) = match fetched {
+            Some(Fetched::Robots(origin, robots)) => {
+                frontier.finish(&origin);
+                frontier.set_robots(&origin, robots);
+                continue;
+            }
+            Some(Fetched::Sitemap(origin, url, sitemap)) => {
+                frontier.finish(&origin);
+                frontier.push_sitemap_entries(&origin, &url, sitemap);
+                continue;
+            }
+            Some(Fetched::Page(origin, url, depth, page)) => {
+                frontier.finish(&origin);
+                match page {
+                    PageFetch::Modified {
+                        html,
+                        etag,
+                        last_modified,
+                    } => (url, depth, html, etag, last_modified),
+                    PageFetch::NotModified => {
+                        if let Some(db) = &db {
+                            if let Ok(Some(mut stored)) = get_entry(db, get_entry_id(url.as_str()))
+                            {
+                                stored.schedule_recrawl(now(), false);
+                                match update_entry(db, &stored) {
+                                    Ok(_) => {
+                                        println!("Unchanged {}", url);
+                                        frontier.visit(&url);
+                                    }
+                                    Err(e) => println!("Error: {:?}. Error storing {}.", e, url),
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    PageFetch::Redirected(target) => {
+                        frontier.push(target, depth);
+                        frontier.visit(&url);
+                        continue;
+                    }
+                    PageFetch::Skipped => {
+                        frontier.visit(&url);
+                        continue;
+                    }
+                    PageFetch::Failed => continue,
+                }
+            }
+            None => continue,
+        };
+
+        let page = explorer.scrape(&url, &Html::parse_document(&html));
+        for link in page.links.iter() {
+            if let Ok(link_url) = Url::parse(&link.url) {
+                frontier.push(link_url, depth + 1);
+            }
+        }
 
-                if let Ok(_) = db.insert(
-                    uuid.as_u128().to_string(),
-                    json::to_string(&crawled_json).unwrap().as_str(),
-                ) {
-                    print!("Crawled {}\n", url);
+        let url_string: String = url.clone().into();
+        let language = match detector.detect_language_of(&page.title) {
+            Some(language) => language.iso_code_639_1().to_string(),
+            None => String::from("unk"),
+        };
+        let mut crawled_json = CrawledEntry {
+            url: url_string,
+            title: page.title,
+            header: page.header,
+            description: page.description,
+            vec: vec![],
+            language,
+            body: page.body,
+            passages: vec![],
+            links: page.links,
+            anchors: vec![],
+            fetched_at: 0,
+            etag,
+            last_modified,
+            recrawl_interval: 0,
+        };
+
+        let stored = match &db {
+            Some(db) => get_entry(db, get_entry_id(&crawled_json.url)).unwrap_or(None),
+            None => None,
+        };
+        match (&db, stored) {
+            // Pages whose validators changed but whose text did not are not embedded again.
+            (Some(db), Some(mut stored)) if stored.has_same_content(&crawled_json) => {
+                stored.etag = crawled_json.etag;
+                stored.last_modified = crawled_json.last_modified;
+                stored.schedule_recrawl(now(), false);
+                match update_entry(db, &stored) {
+                    Ok(_) => {
+                        println!("Unchanged {}", url);
+                        frontier.visit(&url);
+                    }
+                    Err(e) => println!("Error: {:?}. Error storing {}.", e, url),
                 }
+                continue;
+            }
+            (_, Some(stored)) => {
+                crawled_json.recrawl_interval = stored.recrawl_interval;
+                crawled_json.schedule_recrawl(now(), true);
+            }
+            (_, None) => crawled_json.schedule_recrawl(now(), true),
+        }
+
+        if let Some(db) = &db {
+            match get_anchor_texts(db, get_entry_id(&crawled_json.url)) {
+                Ok(anchors) => crawled_json.anchors = anchors,
+                Err(e) => println!("Error: {:?}. Error reading anchor texts of {}.", e, url),
             }
         }
+
+        if sender.send(crawled_json).await.is_err() {
+            break;
+        }
     }
 
+    drop(sender);
+    storing.await?;
     Ok(())
 }
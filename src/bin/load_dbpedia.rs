@@ -0,0 +1,19 @@
+use std::env::{args, var};
+use std::path::Path;
+use tree::dbpedia::{get_dump_path_from_env, load_dump, DEFAULT_LANGUAGE};
+
+/// Loads DBpedia dumps, labels, short abstracts and redirects in N-Triples or Turtle, into the
+/// sled database `KNOWLEDGE_BASE=dump` reads. The files to load are given as arguments, and
+/// `DBPEDIA_DUMP_LANGUAGE` names the language they are in.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let language = var("DBPEDIA_DUMP_LANGUAGE").unwrap_or(String::from(DEFAULT_LANGUAGE));
+    let db = sled::open(get_dump_path_from_env(&language)).expect("open");
+
+    for path in args().skip(1) {
+        let stored = load_dump(&db, Path::new(&path), &language)?;
+        println!("Loaded {} triples from {}", stored, path);
+    }
+    db.flush()?;
+
+    Ok(())
+}
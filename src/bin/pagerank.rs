@@ -0,0 +1,23 @@
+use std::env::var;
+use tree::pagerank::compute_pagerank;
+
+/// Computes the PageRank of every document in the link graph the crawler stored in the url
+/// database. Run it while the server is stopped, after a crawl.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let damping = match var("PAGERANK_DAMPING") {
+        Ok(number) => number.parse().unwrap_or(0.85),
+        Err(_) => 0.85,
+    };
+    let iterations = match var("PAGERANK_ITERATIONS") {
+        Ok(number) => number.parse().unwrap_or(50),
+        Err(_) => 50,
+    };
+
+    let db = sled::open("urlDatabase").expect("open");
+    let ranked = compute_pagerank(&db, damping, iterations)?;
+    db.flush()?;
+
+    println!("Ranked {} pages", ranked);
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use ndarray::{Array, Ix1};
+use rocket::serde::json;
+use tree::anchors::get_anchor_texts;
+use tree::dbpedia::load_dbpedia_from_env;
+use tree::weighting::{
+    compute_common_component, load_weighting_from_env, store_common_component, WeightingMode,
+};
+use tree::{
+    get_entry_embedding, get_entry_id, get_passage_embeddings, load_embedder_from_env, store_entry,
+    CrawledEntry,
+};
+
+/// Embeds every entry of the url database again with the weighting selected by
+/// `EMBEDDING_WEIGHTING`. For SIF the common component is computed from the weighted vectors
+/// of the whole corpus and stored before it is removed from each of them.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let embeddings = load_embedder_from_env().unwrap();
+    let db = sled::open("urlDatabase").expect("open");
+    let weighting = load_weighting_from_env(&db)?;
+    let raw_weighting = weighting.without_common_component();
+    let dbpedia = load_dbpedia_from_env(reqwest::Client::new(), Some(&db));
+
+    let mut entries: Vec<(CrawledEntry, Array<f32, Ix1>)> = Vec::new();
+    for url in db.iter() {
+        let url = url?;
+        match json::from_str::<CrawledEntry>(String::from_utf8_lossy(&url.1).as_ref()) {
+            Ok(mut url_value) => {
+                url_value.anchors = get_anchor_texts(&db, get_entry_id(&url_value.url))?;
+                match get_entry_embedding(&dbpedia, embeddings.as_ref(), &raw_weighting, &url_value)
+                    .await
+                {
+                    Some(vec) => entries.push((url_value, vec)),
+                    None => println!("Error: no embedding for {}, keeping it.", url_value.url),
+                }
+            }
+            Err(e) => {
+                println!("Error: {:?}. URL database deserialization error.", e);
+            }
+        }
+    }
+
+    let weighting = match weighting.mode {
+        WeightingMode::Sif => {
+            let vectors: Vec<Array<f32, Ix1>> =
+                entries.iter().map(|entry| entry.1.clone()).collect();
+            match compute_common_component(&vectors) {
+                Some(component) => {
+                    store_common_component(&db, &component)?;
+                    load_weighting_from_env(&db)?
+                }
+                None => weighting,
+            }
+        }
+        _ => weighting,
+    };
+
+    for (mut url_value, vec) in entries {
+        url_value.vec = weighting.remove_common_component(vec).to_vec();
+        url_value.passages = get_passage_embeddings(embeddings.as_ref(), &weighting, &url_value);
+        store_entry(&db, &url_value)?;
+        println!("Embedded {}", url_value.url);
+    }
+
+    db.flush_async().await?;
+
+    Ok(())
+}
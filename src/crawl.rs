@@ -0,0 +1,296 @@
+use crate::{get_entry_id, get_fetched_at, now};
+use regex::Regex;
+use reqwest::Url;
+use rocket::serde::{json, Deserialize, Serialize};
+use std::env::var;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+static FRONTIER_TREE: &str = "frontier";
+static VISITED_TREE: &str = "visited";
+
+/// Settings of a crawl, read from a TOML file. Settings the file leaves out fall back to the
+/// environment variables the crawler used to be configured with, then to built in defaults.
+/// Unknown settings are rejected, so a misspelled one is not silently left at its default.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlConfig {
+    /// Urls the crawl starts from.
+    pub seeds: Vec<String>,
+    /// File with more seeds, one url per line.
+    pub seed_file: Option<PathBuf>,
+    /// Domains the crawl is kept to, along with their subdomains. Empty allows every domain.
+    pub allow_domains: Vec<String>,
+    /// Domains never crawled, along with their subdomains.
+    pub deny_domains: Vec<String>,
+    /// Regexes one of which urls must match to be crawled. Empty includes every url.
+    pub include: Vec<String>,
+    /// Regexes of urls never crawled.
+    pub exclude: Vec<String>,
+    /// Links followed away from the seeds at most.
+    pub max_depth: usize,
+    /// Pages fetched from a single host at most.
+    pub max_pages_per_host: Option<usize>,
+    pub db_path: String,
+    /// Database the frontier and visited urls are kept in, so a stopped crawl can resume.
+    pub state_path: String,
+    /// Days after which a visited url is crawled again. Without it urls are crawled once.
+    pub recrawl_after_days: Option<u64>,
+    pub embeddings_path: Option<PathBuf>,
+    /// Format of `embeddings_path`, see `EmbeddingsFormat`.
+    pub embeddings_format: Option<String>,
+    pub user_agent: String,
+    pub max_concurrent_requests: usize,
+    pub max_concurrent_requests_per_host: usize,
+    /// Crawled pages embedded and stored at a time, apart from the requests.
+    pub max_concurrent_embeddings: usize,
+    /// Delay between requests to a host that does not ask for a longer one.
+    pub crawl_delay_ms: u64,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        Self {
+            seeds: var("START_URL").into_iter().collect(),
+            seed_file: None,
+            allow_domains: vec![],
+            deny_domains: vec![String::from("facebook.com"), String::from("google.com")],
+            include: vec![],
+            exclude: vec![],
+            max_depth: 4,
+            max_pages_per_host: None,
+            db_path: String::from("urlDatabase"),
+            state_path: String::from("crawlDatabase"),
+            recrawl_after_days: None,
+            embeddings_path: var("EMBEDDINGS_PATH").ok().map(PathBuf::from),
+            embeddings_format: var("EMBEDDINGS_FORMAT").ok(),
+            user_agent: var("USER_AGENT").unwrap_or(format!("tree/{}", env!("CARGO_PKG_VERSION"))),
+            max_concurrent_requests: match var("MAX_CONCURRENT_REQUESTS") {
+                Ok(number) => number.parse().unwrap_or(100),
+                Err(_) => 100,
+            },
+            max_concurrent_requests_per_host: match var("MAX_CONCURRENT_REQUESTS_PER_HOST") {
+                Ok(number) => number.parse().unwrap_or(1),
+                Err(_) => 1,
+            },
+            max_concurrent_embeddings: match var("MAX_CONCURRENT_EMBEDDINGS") {
+                Ok(number) => number.parse().unwrap_or(8),
+                Err(_) => 8,
+            },
+            crawl_delay_ms: match var("CRAWL_DELAY_MS") {
+                Ok(number) => number.parse().unwrap_or(1000),
+                Err(_) => 1000,
+            },
+        }
+    }
+}
+
+impl CrawlConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// `seeds` followed by the urls of `seed_file`, skipping blank lines and `#` comments.
+    pub fn get_seeds(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut seeds = self.seeds.clone();
+        if let Some(seed_file) = &self.seed_file {
+            for line in fs::read_to_string(seed_file)?.lines() {
+                let line = line.trim();
+                if !line.is_empty() && !line.starts_with('#') {
+                    seeds.push(line.to_string());
+                }
+            }
+        }
+        Ok(seeds)
+    }
+}
+
+fn matches_domain(host: &str, domains: &[String]) -> bool {
+    domains
+        .iter()
+        .any(|domain| host == domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Which urls a crawl may visit, as set by the domain lists and regexes of a `CrawlConfig`.
+pub struct UrlFilter {
+    allow_domains: Vec<String>,
+    deny_domains: Vec<String>,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl UrlFilter {
+    pub fn new(config: &CrawlConfig) -> Result<Self, regex::Error> {
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>, regex::Error> {
+            patterns.iter().map(|pattern| Regex::new(pattern)).collect()
+        };
+
+        Ok(Self {
+            allow_domains: config.allow_domains.clone(),
+            deny_domains: config.deny_domains.clone(),
+            include: compile(&config.include)?,
+            exclude: compile(&config.exclude)?,
+        })
+    }
+
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host,
+            None => return false,
+        };
+        if matches_domain(host, &self.deny_domains)
+            || (!self.allow_domains.is_empty() && !matches_domain(host, &self.allow_domains))
+        {
+            return false;
+        }
+
+        let url = url.as_str();
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(url)))
+            && !self.exclude.iter().any(|regex| regex.is_match(url))
+    }
+}
+
+/// Url waiting in the frontier, with the hints of the sitemap that listed it, if any.
+#[derive(Serialize, Deserialize, Default)]
+pub struct QueuedUrl {
+    pub depth: usize,
+    #[serde(default)]
+    pub priority: Option<f32>,
+    /// Last modification the sitemap gave, in seconds since the epoch.
+    #[serde(default)]
+    pub lastmod: Option<u64>,
+}
+
+/// Progress of a crawl kept in sled: the urls waiting to be fetched and when each fetched url
+/// was visited.
+#[derive(Clone)]
+pub struct CrawlState {
+    frontier: sled::Tree,
+    visited: sled::Tree,
+    /// Url database of the pages already indexed, when the crawler can read it.
+    indexed: Option<sled::Db>,
+    recrawl_after: Option<Duration>,
+}
+
+impl CrawlState {
+    pub fn new(
+        db: &sled::Db,
+        indexed: Option<sled::Db>,
+        recrawl_after: Option<Duration>,
+    ) -> sled::Result<Self> {
+        Ok(Self {
+            frontier: db.open_tree(FRONTIER_TREE)?,
+            visited: db.open_tree(VISITED_TREE)?,
+            indexed,
+            recrawl_after,
+        })
+    }
+
+    /// Forgets every queued and visited url, to start the crawl over.
+    pub fn clear(&self) -> sled::Result<()> {
+        self.frontier.clear()?;
+        self.visited.clear()
+    }
+
+    /// Whether `url` should be fetched: it was never visited, or was visited long enough ago
+    /// to be recrawled. Pages indexed before their visit was recorded are recrawled only when
+    /// recrawling is on, as their age is unknown.
+    pub fn is_due(&self, url: &str) -> sled::Result<bool> {
+        match self.get_visited(url)? {
+            Some(visited_at) => Ok(self.is_recrawl_due(visited_at)),
+            None => match &self.indexed {
+                Some(indexed) if indexed.contains_key(get_entry_id(url).to_string())? => {
+                    Ok(self.recrawl_after.is_some())
+                }
+                _ => Ok(true),
+            },
+        }
+    }
+
+    /// Whether a url visited at `visited_at` is old enough to be crawled again.
+    fn is_recrawl_due(&self, visited_at: u64) -> bool {
+        match self.recrawl_after {
+            Some(recrawl_after) => now().saturating_sub(visited_at) >= recrawl_after.as_secs(),
+            None => false,
+        }
+    }
+
+    /// When `url` was last visited, in seconds since the epoch.
+    fn get_visited(&self, url: &str) -> sled::Result<Option<u64>> {
+        Ok(self
+            .visited
+            .get(url)?
+            .map(|visited_at| u64::from_be_bytes(visited_at.as_ref().try_into().unwrap_or([0; 8]))))
+    }
+
+    /// Whether `url` changed since it was last fetched, going by the `lastmod` of a sitemap.
+    fn is_modified(&self, url: &str, lastmod: Option<u64>) -> sled::Result<bool> {
+        let lastmod = match lastmod {
+            Some(lastmod) => lastmod,
+            None => return Ok(false),
+        };
+        let fetched_at = match (self.get_visited(url)?, &self.indexed) {
+            (Some(visited_at), _) => Some(visited_at),
+            (None, Some(indexed)) => get_fetched_at(indexed, get_entry_id(url))?,
+            (None, None) => None,
+        };
+        Ok(matches!(fetched_at, Some(fetched_at) if lastmod > fetched_at))
+    }
+
+    /// Adds `url` to the frontier, returning whether it was not already queued and is due or
+    /// was modified since it was fetched.
+    pub fn queue(&self, url: &str, queued: &QueuedUrl) -> sled::Result<bool> {
+        if !self.is_due(url)? && !self.is_modified(url, queued.lastmod)? {
+            return Ok(false);
+        }
+        self.requeue(url, queued)
+    }
+
+    /// Adds the seed `url` to the frontier unless this crawl visited it recently, whether or
+    /// not it was indexed before, returning whether it was not already queued.
+    pub fn queue_seed(&self, url: &str, queued: &QueuedUrl) -> sled::Result<bool> {
+        match self.get_visited(url)? {
+            Some(visited_at) if !self.is_recrawl_due(visited_at) => Ok(false),
+            _ => self.requeue(url, queued),
+        }
+    }
+
+    /// Adds `url` to the frontier even if it was visited, returning whether it was not already
+    /// queued.
+    pub fn requeue(&self, url: &str, queued: &QueuedUrl) -> sled::Result<bool> {
+        if self.frontier.contains_key(url)? {
+            return Ok(false);
+        }
+        self.frontier
+            .insert(url, json::to_string(queued).unwrap().as_str())?;
+        Ok(true)
+    }
+
+    /// Urls left in the frontier by an earlier run.
+    pub fn get_queued(&self) -> sled::Result<Vec<(String, QueuedUrl)>> {
+        let mut queued = Vec::new();
+        for item in self.frontier.iter() {
+            let (url, value) = item?;
+            if let Ok(entry) = json::from_str::<QueuedUrl>(String::from_utf8_lossy(&value).as_ref())
+            {
+                queued.push((String::from_utf8_lossy(&url).to_string(), entry));
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Takes `url` out of the frontier without visiting it.
+    pub fn remove(&self, url: &str) -> sled::Result<()> {
+        self.frontier.remove(url)?;
+        Ok(())
+    }
+
+    /// Takes `url` out of the frontier and records it as visited now.
+    pub fn visit(&self, url: &str) -> sled::Result<()> {
+        self.visited.insert(url, now().to_be_bytes().to_vec())?;
+        self.frontier.remove(url)?;
+        Ok(())
+    }
+}
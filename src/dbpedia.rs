@@ -1,61 +1,1083 @@
-use rocket::serde::json::Value;
+use crate::now;
+use crate::panel::get_panel_predicates;
+use crate::sparql::{select, Binding, Select, Term, Triple};
+use async_trait::async_trait;
+use rocket::serde::json;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env::var;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
 use titlecase::titlecase;
 
 static SPARQL_ENDPOINT: &str = "http://dbpedia.org/sparql";
+static CACHE_TREE: &str = "dbpedia_cache";
+static LABELS_TREE: &str = "labels";
+static REDIRECTS_TREE: &str = "redirects";
+static SUMMARIES_TREE: &str = "summaries";
+static FACTS_TREE: &str = "facts";
+static MAX_FACTS: usize = 500;
 
-pub async fn get_resource(
-    client: &reqwest::Client,
-    query: &str,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let params = vec![
-        ("query", format!("PREFIX rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#> PREFIX foaf: <http://xmlns.com/foaf/0.1/> PREFIX dbo: <http://dbpedia.org/ontology/>  select ?s WHERE {{ {{ ?s rdfs:label '{}'@en ; a owl:Thing . }} UNION {{ ?altName rdfs:label '{}'@en ; dbo:wikiPageRedirects ?s . }} }}", query, query)),
-        ("output", "json".into())
-    ];
-    let text = client
-        .get(SPARQL_ENDPOINT)
-        .query(&params)
-        .send()
-        .await?
-        .json::<Value>()
-        .await?;
-    //let var_name = &text["head"]["vars"][0].to_string();
-    let resource_redirect = &text["results"]["bindings"][0]["s"]["value"];
-
-    let resource_url: String;
-    if resource_redirect.is_null() {
-        resource_url = format!(
-            "http://dbpedia.org/resource/{}",
-            titlecase(&query).replace(" ", "_")
-        );
+static RDFS_LABEL: &str = "http://www.w3.org/2000/01/rdf-schema#label";
+static RDFS_COMMENT: &str = "http://www.w3.org/2000/01/rdf-schema#comment";
+static DBO_ABSTRACT: &str = "http://dbpedia.org/ontology/abstract";
+static DBO_REDIRECTS: &str = "http://dbpedia.org/ontology/wikiPageRedirects";
+static DBO_DISAMBIGUATES: &str = "http://dbpedia.org/ontology/wikiPageDisambiguates";
+static MAX_CANDIDATES: usize = 10;
+
+/// Language lookups fall back to when there are no knowledge bases for theirs.
+pub static DEFAULT_LANGUAGE: &str = "en";
+/// Languages with knowledge bases of their own besides the default one.
+static KNOWLEDGE_LANGUAGES: [&str; 1] = ["es"];
+
+pub type KnowledgeBaseResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+/// Labels read one at a time, as a dump may hold millions of them.
+pub type Labels<'a> = Box<dyn Iterator<Item = String> + 'a>;
+
+/// Source of the resources and summaries used for query answers and out of vocabulary words.
+/// `Ok(None)` means the knowledge base has nothing for the lookup, errors are failures to ask.
+#[async_trait]
+pub trait KnowledgeBase: Send + Sync {
+    /// Resource labelled `term`, or the target of a redirect labelled `term`.
+    async fn get_resource(&self, term: &str) -> KnowledgeBaseResult<Option<String>>;
+    /// Resources of those of `terms` that have one, see `get_resource`, keyed by the term.
+    /// Knowledge bases behind a remote service look them all up at once.
+    async fn get_resources(
+        &self,
+        terms: &[String],
+    ) -> KnowledgeBaseResult<HashMap<String, String>> {
+        let mut resources = HashMap::new();
+        for term in terms {
+            if let Some(resource) = self.get_resource(term).await? {
+                resources.insert(term.clone(), resource);
+            }
+        }
+        Ok(resources)
+    }
+    /// Every resource `term` may refer to: the ones labelled `term`, the targets of redirects
+    /// labelled `term` and the pages listed by its disambiguation page.
+    async fn get_candidates(&self, term: &str) -> KnowledgeBaseResult<Vec<String>>;
+    /// Short description of a resource.
+    async fn get_summary(&self, resource: &str) -> KnowledgeBaseResult<Option<String>>;
+    /// Values of `predicates` for a resource, as pairs of predicate and value.
+    async fn get_facts(
+        &self,
+        resource: &str,
+        predicates: &[String],
+    ) -> KnowledgeBaseResult<Vec<(String, String)>>;
+    /// Every label the knowledge base can list without asking a remote service.
+    fn get_labels(&self) -> Labels<'_> {
+        Box::new(std::iter::empty())
+    }
+}
+
+/// Suffix Wikipedia adds to the title of disambiguation pages in `language`.
+fn get_disambiguation_suffix(language: &str) -> &'static str {
+    match language {
+        "es" => " (desambiguación)",
+        _ => " (disambiguation)",
+    }
+}
+
+/// Knowledge base asking a remote SPARQL endpoint for labels and summaries in `language`.
+pub struct SparqlKnowledgeBase {
+    client: reqwest::Client,
+    endpoint: String,
+    language: String,
+}
+
+impl SparqlKnowledgeBase {
+    pub fn new(client: reqwest::Client, endpoint: &str, language: &str) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.to_string(),
+            language: language.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ResourceSolution {
+    s: Binding,
+}
+
+#[derive(Deserialize)]
+struct LabelledResourceSolution {
+    label: Binding,
+    s: Binding,
+}
+
+/// Query for the resources labelled with one of `labels`, or redirected to from one.
+fn get_labelled_resource_query(variables: &[&str], labels: Vec<Term>) -> Select {
+    Select::new(variables)
+        .prefix("rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#")
+        .prefix("rdfs", "http://www.w3.org/2000/01/rdf-schema#")
+        .prefix("owl", "http://www.w3.org/2002/07/owl#")
+        .prefix("dbo", "http://dbpedia.org/ontology/")
+        .values("label", labels)
+        .union(vec![
+            vec![
+                Triple(
+                    Term::variable("s"),
+                    Term::Prefixed("rdfs", "label"),
+                    Term::variable("label"),
+                ),
+                Triple(
+                    Term::variable("s"),
+                    Term::Prefixed("rdf", "type"),
+                    Term::Prefixed("owl", "Thing"),
+                ),
+            ],
+            vec![
+                Triple(
+                    Term::variable("altName"),
+                    Term::Prefixed("rdfs", "label"),
+                    Term::variable("label"),
+                ),
+                Triple(
+                    Term::variable("altName"),
+                    Term::Prefixed("dbo", "wikiPageRedirects"),
+                    Term::variable("s"),
+                ),
+            ],
+        ])
+}
+
+#[derive(Deserialize)]
+struct SummarySolution {
+    desc: Binding,
+}
+
+#[derive(Deserialize)]
+struct FactSolution {
+    p: Binding,
+    o: Binding,
+}
+
+#[async_trait]
+impl KnowledgeBase for SparqlKnowledgeBase {
+    async fn get_resource(&self, query: &str) -> KnowledgeBaseResult<Option<String>> {
+        // Labels are matched exactly, so the title cased form is tried as well.
+        let language = Some(self.language.as_str());
+        let mut labels = vec![Term::literal(query, language)];
+        if titlecase(query) != query {
+            labels.push(Term::literal(&titlecase(query), language));
+        }
+
+        let sparql = get_labelled_resource_query(&["s"], labels).limit(1);
+
+        let solutions: Vec<ResourceSolution> =
+            select(&self.client, &self.endpoint, &sparql).await?;
+        Ok(solutions
+            .into_iter()
+            .next()
+            .map(|solution| solution.s.value))
+    }
+
+    async fn get_resources(
+        &self,
+        terms: &[String],
+    ) -> KnowledgeBaseResult<HashMap<String, String>> {
+        if terms.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        // Labels are matched exactly, so the title cased form of every term is asked for as
+        // well, and each label is kept with the term it was asked for.
+        let mut labels: Vec<(String, &String)> = vec![];
+        for term in terms {
+            for label in [term.clone(), titlecase(term)] {
+                if !labels.iter().any(|(known, _)| *known == label) {
+                    labels.push((label, term));
+                }
+            }
+        }
+        let language = Some(self.language.as_str());
+        let sparql = get_labelled_resource_query(
+            &["label", "s"],
+            labels
+                .iter()
+                .map(|(label, _)| Term::literal(label, language))
+                .collect(),
+        )
+        .limit(labels.len() * MAX_CANDIDATES);
+
+        let solutions: Vec<LabelledResourceSolution> =
+            select(&self.client, &self.endpoint, &sparql).await?;
+        let mut resources = HashMap::new();
+        for solution in solutions {
+            if let Some((_, term)) = labels
+                .iter()
+                .find(|(label, _)| *label == solution.label.value)
+            {
+                resources
+                    .entry(term.to_string())
+                    .or_insert(solution.s.value);
+            }
+        }
+        Ok(resources)
+    }
+
+    async fn get_candidates(&self, term: &str) -> KnowledgeBaseResult<Vec<String>> {
+        let mut labels = vec![term.to_string()];
+        if titlecase(term) != term {
+            labels.push(titlecase(term));
+        }
+        let language = Some(self.language.as_str());
+        let suffix = get_disambiguation_suffix(&self.language);
+        let labels: Vec<Term> = labels
+            .iter()
+            .flat_map(|label| {
+                vec![
+                    Term::literal(label, language),
+                    Term::literal(&format!("{}{}", label, suffix), language),
+                ]
+            })
+            .collect();
+
+        let labelled = |predicate: Term| {
+            vec![
+                Triple(
+                    Term::variable("page"),
+                    Term::Prefixed("rdfs", "label"),
+                    Term::variable("label"),
+                ),
+                Triple(Term::variable("page"), predicate, Term::variable("s")),
+            ]
+        };
+        // Resources labelled `term` come first, then redirect targets, then the pages listed
+        // by its disambiguation page, so the limit drops the least direct matches.
+        let sparql = Select::new(&["s"])
+            .prefix("rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#")
+            .prefix("rdfs", "http://www.w3.org/2000/01/rdf-schema#")
+            .prefix("owl", "http://www.w3.org/2002/07/owl#")
+            .prefix("dbo", "http://dbpedia.org/ontology/")
+            .values("label", labels)
+            .ranked_union(
+                "rank",
+                vec![
+                    vec![
+                        Triple(
+                            Term::variable("s"),
+                            Term::Prefixed("rdfs", "label"),
+                            Term::variable("label"),
+                        ),
+                        Triple(
+                            Term::variable("s"),
+                            Term::Prefixed("rdf", "type"),
+                            Term::Prefixed("owl", "Thing"),
+                        ),
+                    ],
+                    labelled(Term::Prefixed("dbo", "wikiPageRedirects")),
+                    labelled(Term::Prefixed("dbo", "wikiPageDisambiguates")),
+                ],
+            )
+            .not_exists(Triple(
+                Term::variable("s"),
+                Term::Prefixed("dbo", "wikiPageDisambiguates"),
+                Term::variable("other"),
+            ))
+            .group_by("s")
+            .order_by_min("rank")
+            .limit(MAX_CANDIDATES);
+
+        let solutions: Vec<ResourceSolution> =
+            select(&self.client, &self.endpoint, &sparql).await?;
+        Ok(solutions
+            .into_iter()
+            .map(|solution| solution.s.value)
+            .collect())
+    }
+
+    async fn get_summary(&self, dbpedia_resource: &str) -> KnowledgeBaseResult<Option<String>> {
+        let sparql = Select::new(&["desc"])
+            .prefix("rdfs", "http://www.w3.org/2000/01/rdf-schema#")
+            .triple(
+                Term::iri(dbpedia_resource),
+                Term::Prefixed("rdfs", "comment"),
+                Term::variable("desc"),
+            )
+            .language("desc", &self.language)
+            .limit(1);
+
+        let solutions: Vec<SummarySolution> = select(&self.client, &self.endpoint, &sparql).await?;
+        Ok(solutions
+            .into_iter()
+            .next()
+            .map(|solution| solution.desc.value))
+    }
+
+    async fn get_facts(
+        &self,
+        resource: &str,
+        predicates: &[String],
+    ) -> KnowledgeBaseResult<Vec<(String, String)>> {
+        let sparql = Select::new(&["p", "o"])
+            .values(
+                "p",
+                predicates
+                    .iter()
+                    .map(|predicate| Term::iri(predicate))
+                    .collect(),
+            )
+            .triple(
+                Term::iri(resource),
+                Term::variable("p"),
+                Term::variable("o"),
+            )
+            .literal_language("o", &self.language)
+            .limit(MAX_FACTS);
+
+        let solutions: Vec<FactSolution> = select(&self.client, &self.endpoint, &sparql).await?;
+        Ok(solutions
+            .into_iter()
+            .map(|solution| (solution.p.value, solution.o.value))
+            .collect())
+    }
+}
+
+/// Knowledge base reading a DBpedia dump loaded into sled with `load_dump`. Labels are stored
+/// lowercased, mapping to their resource, and redirects map a resource to its target. A dump
+/// holds the labels and summaries of a single language.
+pub struct DumpKnowledgeBase {
+    language: String,
+    labels: sled::Tree,
+    redirects: sled::Tree,
+    summaries: sled::Tree,
+    facts: sled::Tree,
+}
+
+impl DumpKnowledgeBase {
+    pub fn new(db: &sled::Db, language: &str) -> sled::Result<Self> {
+        Ok(Self {
+            language: language.to_string(),
+            labels: db.open_tree(LABELS_TREE)?,
+            redirects: db.open_tree(REDIRECTS_TREE)?,
+            summaries: db.open_tree(SUMMARIES_TREE)?,
+            facts: db.open_tree(FACTS_TREE)?,
+        })
+    }
+
+    fn get(tree: &sled::Tree, key: &str) -> sled::Result<Option<String>> {
+        Ok(tree
+            .get(key)?
+            .map(|value| String::from_utf8_lossy(&value).to_string()))
+    }
+
+    fn follow_redirects(&self, resource: String) -> sled::Result<String> {
+        let mut resource = resource;
+        // Redirects are a single hop in DBpedia, the bound only guards against cycles.
+        for _ in 0..4 {
+            match Self::get(&self.redirects, &resource)? {
+                Some(target) => resource = target,
+                None => break,
+            }
+        }
+        Ok(resource)
+    }
+}
+
+#[async_trait]
+impl KnowledgeBase for DumpKnowledgeBase {
+    async fn get_resource(&self, term: &str) -> KnowledgeBaseResult<Option<String>> {
+        match Self::get(&self.labels, &term.to_lowercase())? {
+            Some(resource) => Ok(Some(self.follow_redirects(resource)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_candidates(&self, term: &str) -> KnowledgeBaseResult<Vec<String>> {
+        let disambiguates = [DBO_DISAMBIGUATES.to_string()];
+
+        let mut candidates = Vec::new();
+        for label in [
+            term.to_string(),
+            format!("{}{}", term, get_disambiguation_suffix(&self.language)),
+        ] {
+            let page = match self.get_resource(&label).await? {
+                Some(page) => page,
+                None => continue,
+            };
+
+            let targets = self.get_facts(&page, &disambiguates).await?;
+            let pages = match targets.is_empty() {
+                true => vec![page],
+                false => targets.into_iter().map(|(_, target)| target).collect(),
+            };
+            for page in pages {
+                if !candidates.contains(&page) {
+                    candidates.push(page);
+                }
+            }
+        }
+
+        candidates.truncate(MAX_CANDIDATES);
+        Ok(candidates)
+    }
+
+    async fn get_summary(&self, resource: &str) -> KnowledgeBaseResult<Option<String>> {
+        let resource = self.follow_redirects(resource.to_string())?;
+        Ok(Self::get(&self.summaries, &resource)?)
+    }
+
+    async fn get_facts(
+        &self,
+        resource: &str,
+        predicates: &[String],
+    ) -> KnowledgeBaseResult<Vec<(String, String)>> {
+        let resource = self.follow_redirects(resource.to_string())?;
+
+        let mut facts = Vec::new();
+        for fact in self.facts.scan_prefix(format!("{}\0", resource)) {
+            let (key, _) = fact?;
+            let key = String::from_utf8_lossy(&key).to_string();
+            if let Some((_, predicate, value)) = split_fact_key(&key) {
+                if predicates.iter().any(|known| known == predicate) {
+                    facts.push((predicate.to_string(), value.to_string()));
+                }
+            }
+        }
+        Ok(facts)
+    }
+
+    fn get_labels(&self) -> Labels<'_> {
+        let suffix = get_disambiguation_suffix(&self.language);
+        Box::new(
+            self.labels
+                .iter()
+                .keys()
+                .filter_map(|key| key.ok())
+                .map(|key| String::from_utf8_lossy(&key).to_string())
+                .filter(move |label| !label.ends_with(suffix)),
+        )
+    }
+}
+
+/// Facts of the dump are stored as keys of resource, predicate and value separated by nulls, so
+/// a resource's facts are found with a prefix scan.
+fn get_fact_key(resource: &str, predicate: &str, value: &str) -> String {
+    format!("{}\0{}\0{}", resource, predicate, value)
+}
+
+fn split_fact_key(key: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = key.splitn(3, '\0');
+    Some((parts.next()?, parts.next()?, parts.next()?))
+}
+
+/// Term of a triple, either an iri or a literal with its language tag.
+enum RdfTerm {
+    Iri(String),
+    Literal(String, Option<String>),
+}
+
+/// Reads an iri, `<...>` or a prefixed name, from the start of `input`.
+fn parse_iri<'a>(input: &'a str, prefixes: &HashMap<String, String>) -> Option<(String, &'a str)> {
+    let input = input.trim_start();
+    if let Some(rest) = input.strip_prefix('<') {
+        let end = rest.find('>')?;
+        return Some((rest[..end].to_string(), &rest[end + 1..]));
+    }
+
+    let end = input.find(char::is_whitespace).unwrap_or(input.len());
+    let (prefix, local) = input[..end].split_once(':')?;
+    let namespace = prefixes.get(prefix)?;
+    Some((format!("{}{}", namespace, local), &input[end..]))
+}
+
+/// Reads a quoted literal with its escapes and an optional language tag or datatype.
+fn parse_literal<'a>(
+    input: &'a str,
+    prefixes: &HashMap<String, String>,
+) -> Option<(RdfTerm, &'a str)> {
+    let input = input.trim_start().strip_prefix('"')?;
+
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    let end = loop {
+        match chars.next()? {
+            (position, '"') => break position,
+            (_, '\\') => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'u' => {
+                    let code: String = (0..4).filter_map(|_| chars.next()).map(|c| c.1).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                'U' => {
+                    let code: String = (0..8).filter_map(|_| chars.next()).map(|c| c.1).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                escaped => value.push(escaped),
+            },
+            (_, character) => value.push(character),
+        }
+    };
+
+    let rest = &input[end + 1..];
+    if let Some(rest) = rest.strip_prefix('@') {
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '.')
+            .unwrap_or(rest.len());
+        Some((
+            RdfTerm::Literal(value, Some(rest[..end].to_lowercase())),
+            &rest[end..],
+        ))
+    } else if let Some(rest) = rest.strip_prefix("^^") {
+        let (_, rest) = parse_iri(rest, prefixes)?;
+        Some((RdfTerm::Literal(value, None), rest))
     } else {
-        match resource_redirect.as_str() {
-            Some(url) => resource_url = url.into(),
-            None => return Err("No url resource")?,
+        Some((RdfTerm::Literal(value, None), rest))
+    }
+}
+
+/// Parses a line of N-Triples, or of Turtle written one triple per line as DBpedia dumps are.
+/// `@prefix` lines are added to `prefixes`.
+fn parse_triple(
+    line: &str,
+    prefixes: &mut HashMap<String, String>,
+) -> Option<(String, String, RdfTerm)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    if let Some(declaration) = line
+        .strip_prefix("@prefix")
+        .or_else(|| line.strip_prefix("PREFIX"))
+    {
+        let (prefix, rest) = declaration.trim().split_once(':')?;
+        let (namespace, _) = parse_iri(rest, prefixes)?;
+        prefixes.insert(prefix.trim().to_string(), namespace);
+        return None;
+    }
+
+    let (subject, rest) = parse_iri(line, prefixes)?;
+    let (predicate, rest) = parse_iri(rest, prefixes)?;
+    let (object, _) = match rest.trim_start().starts_with('"') {
+        true => parse_literal(rest, prefixes)?,
+        false => {
+            let (iri, rest) = parse_iri(rest, prefixes)?;
+            (RdfTerm::Iri(iri), rest)
+        }
+    };
+
+    Some((subject, predicate, object))
+}
+
+/// Loads the labels, short abstracts, abstracts, redirects, disambiguations and knowledge panel
+/// facts of a DBpedia dump in N-Triples or Turtle into `db`, skipping literals in other
+/// languages than `language`. Short abstracts win over long ones. Returns the number of triples
+/// stored.
+pub fn load_dump(db: &sled::Db, path: &Path, language: &str) -> Result<usize, Box<dyn Error>> {
+    let labels = db.open_tree(LABELS_TREE)?;
+    let redirects = db.open_tree(REDIRECTS_TREE)?;
+    let summaries = db.open_tree(SUMMARIES_TREE)?;
+    let facts = db.open_tree(FACTS_TREE)?;
+    let panel_predicates = get_panel_predicates();
+
+    let mut prefixes: HashMap<String, String> = HashMap::new();
+    let mut stored = 0;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let (subject, predicate, object) = match parse_triple(&line?, &mut prefixes) {
+            Some(triple) => triple,
+            None => continue,
+        };
+
+        match object {
+            RdfTerm::Literal(value, literal_language)
+                if literal_language
+                    .as_deref()
+                    .map_or(true, |literal_language| literal_language == language) =>
+            {
+                if predicate == RDFS_LABEL {
+                    labels.insert(value.to_lowercase(), subject.as_str())?;
+                    facts.insert(get_fact_key(&subject, &predicate, &value), "")?;
+                } else if predicate == RDFS_COMMENT
+                    || (predicate == DBO_ABSTRACT && !summaries.contains_key(&subject)?)
+                {
+                    summaries.insert(subject.as_str(), value.as_str())?;
+                } else if panel_predicates.contains(&predicate) {
+                    facts.insert(get_fact_key(&subject, &predicate, &value), "")?;
+                } else {
+                    continue;
+                }
+            }
+            RdfTerm::Iri(target) if predicate == DBO_REDIRECTS => {
+                redirects.insert(subject.as_str(), target.as_str())?;
+            }
+            RdfTerm::Iri(value)
+                if panel_predicates.contains(&predicate) || predicate == DBO_DISAMBIGUATES =>
+            {
+                facts.insert(get_fact_key(&subject, &predicate, &value), "")?;
+            }
+            _ => continue,
+        }
+        stored += 1;
+    }
+
+    Ok(stored)
+}
+
+/// Cached outcome of a lookup. `None` records that nothing was found.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    value: Option<String>,
+    fetched_at: u64,
+}
+
+/// Sled backed cache of DBpedia lookups, keyed by term for resources and by resource uri for
+/// summaries.
+pub struct Cache {
+    tree: sled::Tree,
+    ttl: Duration,
+    negative_ttl: Duration,
+}
+
+/// Kinds of lookups, which keys written before they were prefixed by language start with.
+static CACHE_KINDS: [&str; 4] = ["label", "candidates", "summary", "facts"];
+
+impl Cache {
+    /// Opens the cache in `db`, dropping the entries that expired and those written before
+    /// keys were prefixed by language, which are never read again.
+    pub fn new(db: &sled::Db, ttl: Duration, negative_ttl: Duration) -> sled::Result<Self> {
+        let cache = Self {
+            tree: db.open_tree(CACHE_TREE)?,
+            ttl,
+            negative_ttl,
+        };
+        cache.remove_stale()?;
+        Ok(cache)
+    }
+
+    fn remove_stale(&self) -> sled::Result<()> {
+        let now = now();
+        for item in self.tree.iter() {
+            let (key, value) = item?;
+            let unprefixed = match String::from_utf8_lossy(&key).split_once(':') {
+                Some((kind, _)) => CACHE_KINDS.contains(&kind),
+                None => true,
+            };
+            let expired =
+                match json::from_str::<CacheEntry>(String::from_utf8_lossy(&value).as_ref()) {
+                    Ok(entry) => {
+                        let ttl = match entry.value {
+                            Some(_) => self.ttl,
+                            None => self.negative_ttl,
+                        };
+                        now.saturating_sub(entry.fetched_at) > ttl.as_secs()
+                    }
+                    Err(_) => true,
+                };
+            if unprefixed || expired {
+                self.tree.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Cached value for `key`, `Some(None)` for a cached miss and `None` if the lookup has to
+    /// be done again.
+    fn get(&self, key: &str) -> Option<Option<String>> {
+        let value = self.tree.get(key).ok()??;
+        let entry = json::from_str::<CacheEntry>(String::from_utf8_lossy(&value).as_ref()).ok()?;
+
+        let ttl = match entry.value {
+            Some(_) => self.ttl,
+            None => self.negative_ttl,
+        };
+        if now().saturating_sub(entry.fetched_at) > ttl.as_secs() {
+            return None;
+        }
+
+        Some(entry.value)
+    }
+
+    fn insert(&self, key: &str, value: Option<&str>) {
+        let entry = CacheEntry {
+            value: value.map(String::from),
+            fetched_at: now(),
+        };
+        if let Err(e) = self
+            .tree
+            .insert(key, json::to_string(&entry).unwrap().as_str())
+        {
+            println!("Error: {:?}. Error writing DBpedia cache.", e);
+        }
+    }
+
+    /// Terms cached under `kind` lookups, such as `label`, that something was found for.
+    fn get_found_terms(&self, kind: &str) -> Labels<'_> {
+        let infix = format!(":{}:", kind);
+        Box::new(
+            self.tree
+                .iter()
+                .filter_map(|item| item.ok())
+                .filter_map(move |(key, value)| {
+                    let key = String::from_utf8_lossy(&key).to_string();
+                    let (_, term) = key.split_once(&infix)?;
+                    match json::from_str::<CacheEntry>(String::from_utf8_lossy(&value).as_ref()) {
+                        Ok(CacheEntry { value: Some(_), .. }) => Some(term.to_string()),
+                        _ => None,
+                    }
+                }),
+        )
+    }
+}
+
+/// Knowledge base lookups, optionally backed by a `Cache`. Each language has its knowledge
+/// bases, asked in order until one knows the lookup.
+pub struct Dbpedia {
+    knowledge_bases: HashMap<String, Vec<Box<dyn KnowledgeBase>>>,
+    cache: Option<Cache>,
+}
+
+impl Dbpedia {
+    pub fn new(cache: Option<Cache>) -> Self {
+        Self {
+            knowledge_bases: HashMap::new(),
+            cache,
+        }
+    }
+
+    /// Adds `knowledge_base` to the ones asked for lookups in `language`.
+    pub fn with_knowledge_base(
+        mut self,
+        language: &str,
+        knowledge_base: Box<dyn KnowledgeBase>,
+    ) -> Self {
+        self.knowledge_bases
+            .entry(language.to_string())
+            .or_default()
+            .push(knowledge_base);
+        self
+    }
+
+    fn get_knowledge_bases(&self, language: &str) -> (&str, &[Box<dyn KnowledgeBase>]) {
+        match self.knowledge_bases.get_key_value(language) {
+            Some((language, knowledge_bases)) => (language.as_str(), knowledge_bases.as_slice()),
+            None => (
+                DEFAULT_LANGUAGE,
+                self.knowledge_bases
+                    .get(DEFAULT_LANGUAGE)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    /// Labels known without a remote lookup: those of the knowledge bases that can list them
+    /// and the terms already resolved into the cache.
+    pub fn get_labels(&self) -> Labels<'_> {
+        let cached = self.cache.iter().flat_map(|cache| {
+            cache
+                .get_found_terms("label")
+                .chain(cache.get_found_terms("candidates"))
+        });
+        Box::new(
+            self.knowledge_bases
+                .values()
+                .flatten()
+                .flat_map(|knowledge_base| knowledge_base.get_labels())
+                .chain(cached),
+        )
+    }
+
+    fn get_cached(&self, key: &str) -> Option<Option<String>> {
+        self.cache.as_ref().and_then(|cache| cache.get(key))
+    }
+
+    fn insert_cached(&self, key: &str, value: Option<&str>) {
+        if let Some(cache) = &self.cache {
+            cache.insert(key, value);
+        }
+    }
+
+    /// Resources labelled with those of `queries` that a label or redirect matches in
+    /// `language`, keyed by the query. The queries not cached are looked up together.
+    pub async fn find_resources(
+        &self,
+        queries: &[String],
+        language: &str,
+    ) -> HashMap<String, String> {
+        let (language, knowledge_bases) = self.get_knowledge_bases(language);
+        let get_key = |query: &str| format!("{}:label:{}", language, query);
+
+        let mut resources = HashMap::new();
+        let mut missing = vec![];
+        for query in queries {
+            match self.get_cached(&get_key(query)) {
+                Some(Some(resource)) => {
+                    resources.insert(query.clone(), resource);
+                }
+                Some(None) => (),
+                None => missing.push(query.clone()),
+            }
+        }
+
+        let mut failed = false;
+        for knowledge_base in knowledge_bases {
+            if missing.is_empty() {
+                break;
+            }
+            match knowledge_base.get_resources(&missing).await {
+                Ok(found) => {
+                    missing.retain(|query| !found.contains_key(query));
+                    for (query, resource) in found {
+                        self.insert_cached(&get_key(&query), Some(&resource));
+                        resources.insert(query, resource);
+                    }
+                }
+                Err(e) => {
+                    println!("Error: {:?}. Error looking up {:?}.", e, missing);
+                    failed = true;
+                }
+            }
         }
+        // Misses are only cached when every knowledge base could be asked.
+        if !failed {
+            for query in missing {
+                self.insert_cached(&get_key(&query), None);
+            }
+        }
+
+        resources
     }
 
-    Ok(resource_url)
+    /// Resources `query` may refer to in `language`, see `KnowledgeBase::get_candidates`.
+    pub async fn get_candidates(
+        &self,
+        query: &str,
+        language: &str,
+    ) -> KnowledgeBaseResult<Vec<String>> {
+        let (language, knowledge_bases) = self.get_knowledge_bases(language);
+        let key = format!("{}:candidates:{}", language, query);
+        if let Some(candidates) = self.get_cached(&key) {
+            return Ok(candidates
+                .and_then(|candidates| json::from_str(&candidates).ok())
+                .unwrap_or_default());
+        }
+
+        let mut candidates = vec![];
+        let mut error = None;
+        for knowledge_base in knowledge_bases {
+            match knowledge_base.get_candidates(query).await {
+                Ok(found) if !found.is_empty() => {
+                    candidates = found;
+                    break;
+                }
+                Ok(_) => (),
+                Err(e) => error = Some(e),
+            }
+        }
+        if let (true, Some(e)) = (candidates.is_empty(), error) {
+            return Err(e);
+        }
+
+        match candidates.is_empty() {
+            true => self.insert_cached(&key, None),
+            false => self.insert_cached(&key, Some(json::to_string(&candidates).unwrap().as_str())),
+        }
+        Ok(candidates)
+    }
+
+    /// Summary of `dbpedia_resource` in `language`.
+    pub async fn get_summary(
+        &self,
+        dbpedia_resource: &str,
+        language: &str,
+    ) -> KnowledgeBaseResult<String> {
+        let (language, knowledge_bases) = self.get_knowledge_bases(language);
+        let key = format!("{}:summary:{}", language, dbpedia_resource);
+        let summary = match self.get_cached(&key) {
+            Some(summary) => summary,
+            None => {
+                let mut summary = None;
+                let mut error = None;
+                for knowledge_base in knowledge_bases {
+                    match knowledge_base.get_summary(dbpedia_resource).await {
+                        Ok(Some(found)) => {
+                            summary = Some(found);
+                            break;
+                        }
+                        Ok(None) => (),
+                        Err(e) => error = Some(e),
+                    }
+                }
+                if let (None, Some(e)) = (&summary, error) {
+                    return Err(e);
+                }
+
+                self.insert_cached(&key, summary.as_deref());
+                summary
+            }
+        };
+
+        match summary {
+            Some(summary) => Ok(summary),
+            None => Err("No summary found")?,
+        }
+    }
+
+    /// Facts a knowledge panel in `language` is built from, see `panel::get_knowledge_panel`.
+    pub async fn get_facts(
+        &self,
+        resource: &str,
+        language: &str,
+    ) -> KnowledgeBaseResult<Vec<(String, String)>> {
+        let (language, knowledge_bases) = self.get_knowledge_bases(language);
+        let key = format!("{}:facts:{}", language, resource);
+        if let Some(facts) = self.get_cached(&key) {
+            return Ok(facts
+                .and_then(|facts| json::from_str(&facts).ok())
+                .unwrap_or_default());
+        }
+
+        let mut facts = vec![];
+        let mut error = None;
+        for knowledge_base in knowledge_bases {
+            match knowledge_base
+                .get_facts(resource, &get_panel_predicates())
+                .await
+            {
+                Ok(found) if !found.is_empty() => {
+                    facts = found;
+                    break;
+                }
+                Ok(_) => (),
+                Err(e) => error = Some(e),
+            }
+        }
+        if let (true, Some(e)) = (facts.is_empty(), error) {
+            return Err(e);
+        }
+
+        match facts.is_empty() {
+            true => self.insert_cached(&key, None),
+            false => self.insert_cached(&key, Some(json::to_string(&facts).unwrap().as_str())),
+        }
+        Ok(facts)
+    }
+}
+
+/// Sled database the dump of `language` is loaded into, `DBPEDIA_DUMP_PATH` for the default
+/// language and `DBPEDIA_DUMP_PATH_ES` and so on for the others.
+pub fn get_dump_path_from_env(language: &str) -> String {
+    match language {
+        language if language == DEFAULT_LANGUAGE => {
+            var("DBPEDIA_DUMP_PATH").unwrap_or(String::from("dbpediaDatabase"))
+        }
+        language => var(format!("DBPEDIA_DUMP_PATH_{}", language.to_uppercase()))
+            .unwrap_or(format!("dbpediaDatabase_{}", language)),
+    }
+}
+
+/// Knowledge base reading the dump of `language`, `None` if it was never loaded or cannot be
+/// opened.
+fn open_dump(language: &str) -> Option<DumpKnowledgeBase> {
+    let dump_path = get_dump_path_from_env(language);
+    // Opening a missing path would create an empty database and answer nothing.
+    if !Path::new(&dump_path).exists() {
+        println!("No DBpedia dump of {} found at {}.", language, dump_path);
+        return None;
+    }
+
+    match sled::open(&dump_path).and_then(|dump| DumpKnowledgeBase::new(&dump, language)) {
+        Ok(knowledge_base) => Some(knowledge_base),
+        Err(e) => {
+            println!(
+                "Error: {:?}. Error opening DBpedia dump at {}.",
+                e, dump_path
+            );
+            None
+        }
+    }
+}
+
+fn open_cache(db: &sled::Db) -> Option<Cache> {
+    let ttl = Duration::from_secs(match var("DBPEDIA_CACHE_TTL") {
+        Ok(number) => number.parse().unwrap_or(30 * 24 * 60 * 60),
+        Err(_) => 30 * 24 * 60 * 60,
+    });
+    let negative_ttl = Duration::from_secs(match var("DBPEDIA_NEGATIVE_TTL") {
+        Ok(number) => number.parse().unwrap_or(24 * 60 * 60),
+        Err(_) => 24 * 60 * 60,
+    });
+
+    match Cache::new(db, ttl, negative_ttl) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            println!("Error: {:?}. Error opening DBpedia cache, not caching.", e);
+            None
+        }
+    }
 }
 
-pub async fn get_summary(
+/// SPARQL knowledge bases asked for lookups in `language`: `SPARQL_ENDPOINT`, then the
+/// endpoint of the language itself for languages other than the default.
+fn get_sparql_knowledge_bases(
     client: &reqwest::Client,
-    dbpedia_resource: &str,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let params = vec![
-        ("query", format!("select str(?desc) where {{ <{}> rdfs:comment ?desc filter (langMatches(lang(?desc),'en')) }}", dbpedia_resource)),
-        ("output", "json".to_string())
-    ];
-    let text = client
-        .get(SPARQL_ENDPOINT)
-        .query(&params)
-        .send()
-        .await?
-        .json::<Value>()
-        .await?;
-    //let var_name = &text["head"]["vars"][0].to_string();
-
-    match text["results"]["bindings"][0]["callret-0"]["value"].as_str() {
-        Some(summary) => Ok(summary.into()),
-        None => Err("No summary found")?,
+    language: &str,
+) -> Vec<Box<dyn KnowledgeBase>> {
+    let endpoint = var("SPARQL_ENDPOINT").unwrap_or(String::from(SPARQL_ENDPOINT));
+    let mut knowledge_bases: Vec<Box<dyn KnowledgeBase>> = vec![Box::new(
+        SparqlKnowledgeBase::new(client.clone(), &endpoint, language),
+    )];
+    if language != DEFAULT_LANGUAGE {
+        let language_endpoint = var(format!("SPARQL_ENDPOINT_{}", language.to_uppercase()))
+            .unwrap_or(format!("http://{}.dbpedia.org/sparql", language));
+        knowledge_bases.push(Box::new(SparqlKnowledgeBase::new(
+            client.clone(),
+            &language_endpoint,
+            language,
+        )));
+    }
+    knowledge_bases
+}
+
+/// `Dbpedia` lookups against the knowledge bases selected by `KNOWLEDGE_BASE`.
+///
+/// With `sparql`, the default, `SPARQL_ENDPOINT` is asked in every language, then the endpoint
+/// of the language itself, `SPARQL_ENDPOINT_ES` for Spanish, defaulting to its DBpedia chapter.
+/// Lookups are cached in `db`, if given, for `DBPEDIA_CACHE_TTL` seconds or
+/// `DBPEDIA_NEGATIVE_TTL` when nothing was found.
+///
+/// With `dump`, the dump of each language loaded by `load_dbpedia` is read, see
+/// `get_dump_path_from_env`. Languages without a dump are looked up with SPARQL.
+pub fn load_dbpedia_from_env(client: reqwest::Client, db: Option<&sled::Db>) -> Dbpedia {
+    let dump = matches!(var("KNOWLEDGE_BASE").as_deref(), Ok("dump"));
+
+    let mut knowledge_bases: Vec<(&str, Vec<Box<dyn KnowledgeBase>>)> = vec![];
+    let mut remote = false;
+    for language in [DEFAULT_LANGUAGE].iter().chain(KNOWLEDGE_LANGUAGES.iter()) {
+        match dump.then(|| open_dump(language)).flatten() {
+            Some(knowledge_base) => knowledge_bases.push((
+                *language,
+                vec![Box::new(knowledge_base) as Box<dyn KnowledgeBase>],
+            )),
+            None => {
+                if dump {
+                    println!("Looking DBpedia up with SPARQL in {} instead.", language);
+                }
+                remote = true;
+                knowledge_bases.push((*language, get_sparql_knowledge_bases(&client, language)));
+            }
+        }
+    }
+
+    // Dumps are local, so only remote lookups are worth caching.
+    let cache = match db {
+        Some(db) if remote => open_cache(db),
+        _ => None,
+    };
+    let mut dbpedia = Dbpedia::new(cache);
+    for (language, language_knowledge_bases) in knowledge_bases {
+        for knowledge_base in language_knowledge_bases {
+            dbpedia = dbpedia.with_knowledge_base(language, knowledge_base);
+        }
     }
+    dbpedia
 }
@@ -0,0 +1,173 @@
+use crate::dbpedia::Dbpedia;
+use crate::weighting::Weighting;
+use crate::{get_chunk_embedding, get_word_embedding, Embedder};
+use ndarray::{Array, Ix1};
+
+/// Longest run of words looked up as a single entity.
+pub static MAX_ENTITY_WORDS: usize = 4;
+
+/// Run of words of a sentence that names a DBpedia resource.
+pub struct Entity {
+    /// Index of the first word of the entity in the whitespace split sentence.
+    pub start: usize,
+    /// Index one past its last word.
+    pub end: usize,
+    pub text: String,
+    pub resource: String,
+}
+
+impl Entity {
+    pub fn word_count(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+fn clean_word(word: &str) -> &str {
+    word.trim_matches(|c: char| !c.is_alphanumeric())
+}
+
+fn get_cosine_similarity(a: &Array<f32, Ix1>, b: &Array<f32, Ix1>) -> f32 {
+    let norms = a.dot(a).sqrt() * b.dot(b).sqrt();
+    match norms {
+        norms if norms > 0.0 => a.dot(b) / norms,
+        _ => 0.0,
+    }
+}
+
+/// Readable name of a DBpedia resource, the title of its page, such as `Rust (fungus)`.
+fn get_resource_name(resource: &str) -> String {
+    resource
+        .rsplit('/')
+        .next()
+        .unwrap_or(resource)
+        .replace('_', " ")
+}
+
+/// Resource `text`, in `language`, refers to in the context of `context`, the candidate whose
+/// name is most similar to it. Names of ambiguous pages carry what sets them apart, such as
+/// `Mercury (planet)`, so candidates are told apart without fetching their summaries. Without
+/// a context, or names to compare, the first candidate is taken.
+pub async fn resolve_entity(
+    dbpedia: &Dbpedia,
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
+    text: &str,
+    language: &str,
+    context: Option<&Array<f32, Ix1>>,
+) -> Option<String> {
+    let candidates = match dbpedia.get_candidates(text, language).await {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            println!("Error: {:?}. Error fetching candidates of \"{}\".", e, text);
+            return None;
+        }
+    };
+
+    let context = match context {
+        Some(context) if candidates.len() > 1 => context,
+        _ => return candidates.into_iter().next(),
+    };
+
+    let mut best: Option<(f32, &String)> = None;
+    for candidate in &candidates {
+        let name = get_resource_name(candidate);
+        let similarity = match get_chunk_embedding(embeddings, weighting, &name) {
+            Some(embedding) => get_cosine_similarity(&embedding, context),
+            None => continue,
+        };
+
+        if best.map_or(true, |(best_similarity, _)| similarity > best_similarity) {
+            best = Some((similarity, candidate));
+        }
+    }
+
+    match best {
+        Some((_, candidate)) => Some(candidate.clone()),
+        None => candidates.into_iter().next(),
+    }
+}
+
+/// Links the entities of `sentence` from left to right, preferring the longest run of words
+/// matching a label or redirect. Single words are only looked up when `embeddings` does not
+/// know them, as known words are embedded well enough on their own. Every run is looked up in
+/// a single request, and only the runs linked are disambiguated, against the known words of
+/// the whole sentence. Labels are matched in `language`.
+pub async fn link_entities(
+    dbpedia: &Dbpedia,
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
+    sentence: &str,
+    language: &str,
+) -> Vec<Entity> {
+    let words: Vec<&str> = sentence.split_whitespace().map(clean_word).collect();
+    let context = get_chunk_embedding(embeddings, weighting, sentence);
+
+    let is_linkable = |span: &[&str]| {
+        !span.iter().any(|word| word.is_empty())
+            && !(span.len() == 1 && get_word_embedding(embeddings, span[0]).is_some())
+    };
+    let mut texts: Vec<String> = vec![];
+    for start in 0..words.len() {
+        for end in start + 1..=(start + MAX_ENTITY_WORDS).min(words.len()) {
+            let span = &words[start..end];
+            let text = span.join(" ");
+            if is_linkable(span) && !texts.contains(&text) {
+                texts.push(text);
+            }
+        }
+    }
+    let resources = dbpedia.find_resources(&texts, language).await;
+
+    let mut entities = Vec::new();
+    let mut start = 0;
+    while start < words.len() {
+        let mut matched = None;
+        for end in (start + 1..=(start + MAX_ENTITY_WORDS).min(words.len())).rev() {
+            let span = &words[start..end];
+            if !is_linkable(span) {
+                continue;
+            }
+
+            let text = span.join(" ");
+            if let Some(resource) = resources.get(&text) {
+                matched = Some((end, text, resource.clone()));
+                break;
+            }
+        }
+
+        match matched {
+            Some((end, text, resource)) => {
+                let resource = resolve_entity(
+                    dbpedia,
+                    embeddings,
+                    weighting,
+                    &text,
+                    language,
+                    context.as_ref(),
+                )
+                .await
+                .unwrap_or(resource);
+                entities.push(Entity {
+                    start,
+                    end,
+                    text,
+                    resource,
+                });
+                start = end;
+            }
+            None => start += 1,
+        }
+    }
+
+    entities
+}
+
+/// Entity a query is most likely about, the longest one linked in it.
+pub fn get_main_entity(entities: &[Entity]) -> Option<&Entity> {
+    entities
+        .iter()
+        .fold(None, |main: Option<&Entity>, entity| match main {
+            Some(main) if main.word_count() >= entity.word_count() => Some(main),
+            _ => Some(entity),
+        })
+}
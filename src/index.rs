@@ -0,0 +1,204 @@
+use crate::{get_passage_id, CrawledEntry};
+use hora::core::ann_index::{ANNIndex, SerializableIndex};
+use hora::core::metrics::Metric;
+use hora::index::{hnsw_idx::HNSWIndex, hnsw_params::HNSWParams};
+use rocket::serde::{json, Deserialize, Serialize};
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Describes the state of the url database a snapshot was built from.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct SnapshotMeta {
+    dimension: usize,
+    entries: usize,
+    checksum: u32,
+}
+
+fn get_snapshot_meta(db: &sled::Db, dimension: usize) -> sled::Result<SnapshotMeta> {
+    // Only the entries hold vectors, so writes to the other trees, such as cached knowledge
+    // base lookups, leave the snapshot valid.
+    let entries: &sled::Tree = db;
+    Ok(SnapshotMeta {
+        dimension,
+        entries: entries.len(),
+        checksum: entries.checksum()?,
+    })
+}
+
+fn get_meta_path(snapshot_path: &str) -> String {
+    format!("{}.json", snapshot_path)
+}
+
+pub fn build_index(db: &sled::Db, dimension: usize) -> HNSWIndex<f32, u128> {
+    let mut vec_index = HNSWIndex::<f32, u128>::new(dimension, &HNSWParams::<f32>::default());
+
+    for url in db.iter() {
+        if let Ok(url) = url {
+            let url_key: u128 = String::from_utf8_lossy(&url.0).parse().unwrap();
+            match json::from_str::<CrawledEntry>(String::from_utf8_lossy(&url.1).as_ref()) {
+                Ok(url_value) => {
+                    if url_value.vec.len() != dimension {
+                        println!(
+                            "Error: entry {} has {} dimensions, expected {}. Recrawl it with the current embeddings.",
+                            url_value.url,
+                            url_value.vec.len(),
+                            dimension
+                        );
+                        continue;
+                    }
+                    vec_index.add(&url_value.vec, url_key).unwrap();
+
+                    for (index, passage) in url_value.passages.iter().enumerate() {
+                        if passage.len() == dimension {
+                            vec_index
+                                .add(passage, get_passage_id(&url_value.url, index))
+                                .unwrap();
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("Error: {:?}. URL database deserialization error.", e);
+                }
+            }
+        }
+    }
+
+    vec_index.build(Metric::CosineSimilarity).unwrap();
+    vec_index
+}
+
+/// Loads the index snapshot at `snapshot_path` if it was built from the current contents of
+/// `db`, otherwise rebuilds the index from `db` and writes a fresh snapshot.
+pub fn load_or_build_index(
+    db: &sled::Db,
+    snapshot_path: &str,
+    dimension: usize,
+) -> HNSWIndex<f32, u128> {
+    let meta = match get_snapshot_meta(db, dimension) {
+        Ok(meta) => Some(meta),
+        Err(e) => {
+            println!("Error: {:?}. Error computing URL database checksum.", e);
+            None
+        }
+    };
+
+    if let Some(meta) = &meta {
+        let stored_meta = fs::read_to_string(get_meta_path(snapshot_path))
+            .ok()
+            .and_then(|value| json::from_str::<SnapshotMeta>(&value).ok());
+
+        if stored_meta.as_ref() == Some(meta) {
+            match HNSWIndex::<f32, u128>::load(snapshot_path) {
+                Ok(vec_index) => return vec_index,
+                Err(e) => {
+                    println!("Error: {:?}. Error loading index snapshot, rebuilding.", e);
+                }
+            }
+        } else {
+            println!("Index snapshot is missing or stale, rebuilding.");
+        }
+    }
+
+    build_snapshot(db, snapshot_path, dimension)
+}
+
+/// Rebuilds the index from `db` and writes it to `snapshot_path`.
+pub fn build_snapshot(
+    db: &sled::Db,
+    snapshot_path: &str,
+    dimension: usize,
+) -> HNSWIndex<f32, u128> {
+    let meta = get_snapshot_meta(db, dimension);
+    let mut vec_index = build_index(db, dimension);
+
+    match meta {
+        Ok(meta) => match vec_index.dump(snapshot_path) {
+            Ok(_) => {
+                if let Err(e) = fs::write(
+                    get_meta_path(snapshot_path),
+                    json::to_string(&meta).unwrap(),
+                ) {
+                    println!("Error: {:?}. Error writing index snapshot metadata.", e);
+                }
+            }
+            Err(e) => {
+                println!("Error: {:?}. Error writing index snapshot.", e);
+            }
+        },
+        Err(e) => {
+            println!("Error: {:?}. Error computing URL database checksum.", e);
+        }
+    }
+
+    vec_index
+}
+
+/// Index that can be swapped while queries are being served. Readers take a cheap handle to
+/// the current index, so a rebuild never blocks a search.
+pub struct LiveIndex {
+    current: RwLock<Arc<HNSWIndex<f32, u128>>>,
+}
+
+impl LiveIndex {
+    pub fn new(vec_index: HNSWIndex<f32, u128>) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(vec_index)),
+        }
+    }
+
+    pub fn current(&self) -> Arc<HNSWIndex<f32, u128>> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, vec_index: HNSWIndex<f32, u128>) {
+        *self.current.write().unwrap() = Arc::new(vec_index);
+    }
+}
+
+/// Watches `db` for inserted, updated and removed entries and swaps in a rebuilt index once
+/// writes have been quiet for `debounce`, or `max_wait` after the first change when writes
+/// keep coming, as during a crawl.
+///
+/// The index cannot remove or replace vectors, so every rebuild reads all entries of `db`
+/// again. `max_wait` bounds how stale the index gets, and how often that cost is paid.
+pub async fn watch_index(
+    db: sled::Db,
+    live_index: Arc<LiveIndex>,
+    snapshot_path: String,
+    dimension: usize,
+    debounce: Duration,
+    max_wait: Duration,
+) {
+    let mut subscriber = db.watch_prefix(vec![]);
+
+    while (&mut subscriber).await.is_some() {
+        let deadline = Instant::now() + max_wait;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match tokio::time::timeout(debounce.min(deadline - now), &mut subscriber).await {
+                Ok(Some(_)) => continue,
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        let db = db.clone();
+        let snapshot_path = snapshot_path.clone();
+        match tokio::task::spawn_blocking(move || build_snapshot(&db, &snapshot_path, dimension))
+            .await
+        {
+            Ok(vec_index) => {
+                live_index.replace(vec_index);
+                println!("Index rebuilt after URL database changes.");
+            }
+            Err(e) => {
+                println!("Error: {:?}. Error rebuilding index.", e);
+            }
+        }
+    }
+}
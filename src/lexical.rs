@@ -0,0 +1,368 @@
+use crate::CrawledEntry;
+use rocket::serde::{json, Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+static POSTINGS_TREE: &str = "lexical_postings";
+static DOCUMENTS_TREE: &str = "lexical_documents";
+/// Length of every indexed document, read on each search without decoding its terms.
+static LENGTHS_TREE: &str = "lexical_lengths";
+static TERMS_TREE: &str = "lexical_terms";
+static STATS_TREE: &str = "lexical_stats";
+static DOCUMENT_COUNT_KEY: &str = "documents";
+static TOTAL_LENGTH_KEY: &str = "length";
+
+static BM25_K1: f32 = 1.2;
+static BM25_B: f32 = 0.75;
+
+/// Terms of an indexed document, kept so its postings can be removed when it changes.
+#[derive(Serialize, Deserialize)]
+struct LexicalDocument {
+    length: u64,
+    terms: HashMap<String, u32>,
+}
+
+/// Document scored by a search, ordered by score so the best ones can be kept in a heap.
+struct ScoredDocument {
+    score: f32,
+    id: u128,
+}
+
+impl PartialEq for ScoredDocument {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScoredDocument {}
+
+impl PartialOrd for ScoredDocument {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScoredDocument {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+/// How often a term shows up in the indexed corpus.
+#[derive(Default)]
+pub struct TermStatistics {
+    pub document_frequency: u64,
+    pub count: u64,
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+fn get_posting_key(term: &str, id: u128) -> String {
+    format!("{}\0{}", term, id)
+}
+
+fn get_counter(stats: &sled::Tree, key: &str) -> sled::Result<u64> {
+    Ok(match stats.get(key)? {
+        Some(value) => u64::from_be_bytes(value.as_ref().try_into().unwrap_or([0; 8])),
+        None => 0,
+    })
+}
+
+fn add_to_counter(stats: &sled::Tree, key: &str, delta: i64) -> sled::Result<()> {
+    stats.update_and_fetch(key, |value| {
+        let current = match value {
+            Some(value) => u64::from_be_bytes(value.try_into().unwrap_or([0; 8])),
+            None => 0,
+        };
+        Some((current as i64 + delta).max(0).to_be_bytes().to_vec())
+    })?;
+    Ok(())
+}
+
+fn update_term_statistics(
+    terms: &sled::Tree,
+    term: &str,
+    document_delta: i64,
+    count_delta: i64,
+) -> sled::Result<()> {
+    terms.update_and_fetch(term, |value| {
+        let (document_frequency, count) = match value {
+            Some(value) if value.len() == 16 => (
+                u64::from_be_bytes(value[..8].try_into().unwrap()),
+                u64::from_be_bytes(value[8..].try_into().unwrap()),
+            ),
+            _ => (0, 0),
+        };
+        let document_frequency = (document_frequency as i64 + document_delta).max(0) as u64;
+        let count = (count as i64 + count_delta).max(0) as u64;
+        if document_frequency == 0 {
+            return None;
+        }
+
+        let mut value = document_frequency.to_be_bytes().to_vec();
+        value.extend_from_slice(&count.to_be_bytes());
+        Some(value)
+    })?;
+    Ok(())
+}
+
+pub fn get_term_statistics(db: &sled::Db, term: &str) -> sled::Result<TermStatistics> {
+    let terms = db.open_tree(TERMS_TREE)?;
+    Ok(match terms.get(term)? {
+        Some(value) if value.len() == 16 => TermStatistics {
+            document_frequency: u64::from_be_bytes(value[..8].try_into().unwrap()),
+            count: u64::from_be_bytes(value[8..].try_into().unwrap()),
+        },
+        _ => TermStatistics::default(),
+    })
+}
+
+/// Statistics of every term in the index.
+pub fn get_all_term_statistics(db: &sled::Db) -> sled::Result<Vec<(String, TermStatistics)>> {
+    let terms = db.open_tree(TERMS_TREE)?;
+    let mut statistics = Vec::new();
+    for term in terms.iter() {
+        let (key, value) = term?;
+        if value.len() == 16 {
+            statistics.push((
+                String::from_utf8_lossy(&key).to_string(),
+                TermStatistics {
+                    document_frequency: u64::from_be_bytes(value[..8].try_into().unwrap()),
+                    count: u64::from_be_bytes(value[8..].try_into().unwrap()),
+                },
+            ));
+        }
+    }
+    Ok(statistics)
+}
+
+/// Number of indexed documents and of terms across all of them.
+pub fn get_corpus_statistics(db: &sled::Db) -> sled::Result<(u64, u64)> {
+    let stats = db.open_tree(STATS_TREE)?;
+    Ok((
+        get_counter(&stats, DOCUMENT_COUNT_KEY)?,
+        get_counter(&stats, TOTAL_LENGTH_KEY)?,
+    ))
+}
+
+/// Drops `id` from the inverted index, if it was indexed.
+pub fn remove_document(db: &sled::Db, id: u128) -> sled::Result<()> {
+    let documents = db.open_tree(DOCUMENTS_TREE)?;
+    let postings = db.open_tree(POSTINGS_TREE)?;
+    let terms = db.open_tree(TERMS_TREE)?;
+    let stats = db.open_tree(STATS_TREE)?;
+
+    db.open_tree(LENGTHS_TREE)?.remove(id.to_string())?;
+    if let Some(value) = documents.remove(id.to_string())? {
+        if let Ok(document) =
+            json::from_str::<LexicalDocument>(String::from_utf8_lossy(&value).as_ref())
+        {
+            for (term, frequency) in document.terms.iter() {
+                postings.remove(get_posting_key(term, id))?;
+                update_term_statistics(&terms, term, -1, -(*frequency as i64))?;
+            }
+            add_to_counter(&stats, DOCUMENT_COUNT_KEY, -1)?;
+            add_to_counter(&stats, TOTAL_LENGTH_KEY, -(document.length as i64))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Indexes the title, header, description, body and anchor texts of `entry` under `id`. A
+/// previous version of the document is updated in place, only touching the terms that changed,
+/// so the document stays searchable throughout.
+pub fn index_document(db: &sled::Db, id: u128, entry: &CrawledEntry) -> sled::Result<()> {
+    let postings = db.open_tree(POSTINGS_TREE)?;
+    let documents = db.open_tree(DOCUMENTS_TREE)?;
+    let term_statistics = db.open_tree(TERMS_TREE)?;
+    let stats = db.open_tree(STATS_TREE)?;
+
+    let tokens = tokenize(&format!(
+        "{} {} {} {} {}",
+        entry.title,
+        entry.header,
+        entry.description,
+        entry.body,
+        entry.anchors.join(" ")
+    ));
+    let mut terms: HashMap<String, u32> = HashMap::new();
+    for token in tokens.iter() {
+        *terms.entry(token.clone()).or_default() += 1;
+    }
+
+    let previous = match documents.get(id.to_string())? {
+        Some(value) => {
+            json::from_str::<LexicalDocument>(String::from_utf8_lossy(&value).as_ref()).ok()
+        }
+        None => None,
+    };
+    let previous_terms = previous
+        .as_ref()
+        .map(|document| &document.terms)
+        .cloned()
+        .unwrap_or_default();
+
+    let mut batch = sled::Batch::default();
+    for (term, frequency) in terms.iter() {
+        batch.insert(
+            get_posting_key(term, id).as_str(),
+            frequency.to_be_bytes().to_vec(),
+        );
+        match previous_terms.get(term) {
+            Some(previous_frequency) if previous_frequency == frequency => (),
+            Some(previous_frequency) => update_term_statistics(
+                &term_statistics,
+                term,
+                0,
+                *frequency as i64 - *previous_frequency as i64,
+            )?,
+            None => update_term_statistics(&term_statistics, term, 1, *frequency as i64)?,
+        }
+    }
+    for (term, previous_frequency) in previous_terms.iter() {
+        if !terms.contains_key(term) {
+            batch.remove(get_posting_key(term, id).as_str());
+            update_term_statistics(&term_statistics, term, -1, -(*previous_frequency as i64))?;
+        }
+    }
+    postings.apply_batch(batch)?;
+
+    let document = LexicalDocument {
+        length: tokens.len() as u64,
+        terms,
+    };
+    documents.insert(id.to_string(), json::to_string(&document).unwrap().as_str())?;
+    db.open_tree(LENGTHS_TREE)?
+        .insert(id.to_string(), document.length.to_be_bytes().to_vec())?;
+    match &previous {
+        Some(previous) => add_to_counter(
+            &stats,
+            TOTAL_LENGTH_KEY,
+            document.length as i64 - previous.length as i64,
+        )?,
+        None => {
+            add_to_counter(&stats, DOCUMENT_COUNT_KEY, 1)?;
+            add_to_counter(&stats, TOTAL_LENGTH_KEY, document.length as i64)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Indexes every entry of the url database if the inverted index has not been built yet, for
+/// databases crawled before lexical search existed, and fills in the document lengths of
+/// indexes built before they were kept apart.
+pub fn ensure_lexical_index(db: &sled::Db) -> sled::Result<()> {
+    let stats = db.open_tree(STATS_TREE)?;
+    if get_counter(&stats, DOCUMENT_COUNT_KEY)? > 0 {
+        let lengths = db.open_tree(LENGTHS_TREE)?;
+        if lengths.is_empty() {
+            for document in db.open_tree(DOCUMENTS_TREE)?.iter() {
+                let (key, value) = document?;
+                if let Ok(document) =
+                    json::from_str::<LexicalDocument>(String::from_utf8_lossy(&value).as_ref())
+                {
+                    lengths.insert(key, document.length.to_be_bytes().to_vec())?;
+                }
+            }
+        }
+        return Ok(());
+    }
+    if db.is_empty() {
+        return Ok(());
+    }
+
+    for url in db.iter() {
+        let url = url?;
+        let url_key: u128 = match String::from_utf8_lossy(&url.0).parse() {
+            Ok(url_key) => url_key,
+            Err(_) => continue,
+        };
+        match json::from_str::<CrawledEntry>(String::from_utf8_lossy(&url.1).as_ref()) {
+            Ok(url_value) => index_document(db, url_key, &url_value)?,
+            Err(e) => {
+                println!("Error: {:?}. URL database deserialization error.", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Scores every document containing at least one term of `query` with BM25, returning the
+/// `limit` best ones, best first.
+pub fn search_bm25(db: &sled::Db, query: &str, limit: usize) -> sled::Result<Vec<(u128, f32)>> {
+    let postings = db.open_tree(POSTINGS_TREE)?;
+    let lengths = db.open_tree(LENGTHS_TREE)?;
+    let stats = db.open_tree(STATS_TREE)?;
+
+    let document_count = get_counter(&stats, DOCUMENT_COUNT_KEY)? as f32;
+    if document_count == 0.0 {
+        return Ok(vec![]);
+    }
+    let average_length = get_counter(&stats, TOTAL_LENGTH_KEY)? as f32 / document_count;
+
+    let mut query_terms = tokenize(query);
+    query_terms.sort();
+    query_terms.dedup();
+
+    let mut known_lengths: HashMap<u128, f32> = HashMap::new();
+    let mut scores: HashMap<u128, f32> = HashMap::new();
+    for term in query_terms.iter() {
+        let mut matches: Vec<(u128, f32)> = Vec::new();
+        for posting in postings.scan_prefix(format!("{}\0", term)) {
+            let (key, value) = posting?;
+            let id = String::from_utf8_lossy(&key[term.len() + 1..]).parse::<u128>();
+            let frequency = <[u8; 4]>::try_from(value.as_ref()).map(u32::from_be_bytes);
+            if let (Ok(id), Ok(frequency)) = (id, frequency) {
+                matches.push((id, frequency as f32));
+            }
+        }
+
+        let document_frequency = matches.len() as f32;
+        let idf =
+            (1.0 + (document_count - document_frequency + 0.5) / (document_frequency + 0.5)).ln();
+
+        for (id, frequency) in matches {
+            let length = match known_lengths.get(&id) {
+                Some(length) => *length,
+                None => {
+                    let length = match lengths.get(id.to_string())? {
+                        Some(value) => <[u8; 8]>::try_from(value.as_ref())
+                            .map(|length| u64::from_be_bytes(length) as f32)
+                            .unwrap_or(average_length),
+                        None => average_length,
+                    };
+                    known_lengths.insert(id, length);
+                    length
+                }
+            };
+
+            *scores.entry(id).or_default() += idf * frequency * (BM25_K1 + 1.0)
+                / (frequency + BM25_K1 * (1.0 - BM25_B + BM25_B * length / average_length));
+        }
+    }
+
+    // A min-heap of the best `limit` documents so far, its root the first to go.
+    let mut best: BinaryHeap<Reverse<ScoredDocument>> = BinaryHeap::with_capacity(limit + 1);
+    for (id, score) in scores {
+        best.push(Reverse(ScoredDocument { score, id }));
+        if best.len() > limit {
+            best.pop();
+        }
+    }
+
+    Ok(best
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(document)| (document.id, document.score))
+        .collect())
+}
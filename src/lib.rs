@@ -1,8 +1,38 @@
-use finalfusion::{embeddings::Embeddings, storage::NdArray, vocab::SimpleVocab};
+use crate::dbpedia::Dbpedia;
+use crate::entities::{link_entities, Entity};
+use crate::spelling::Speller;
+use crate::weighting::{Weighting, WeightingMode};
+use finalfusion::compat::text::{ReadText, ReadTextDims};
+use finalfusion::compat::word2vec::ReadWord2Vec;
+use finalfusion::embeddings::Embeddings;
+use finalfusion::io::{MmapEmbeddings, ReadEmbeddings};
+use finalfusion::storage::{NdArray, Storage, StorageWrap};
+use finalfusion::vocab::{SimpleVocab, Vocab, VocabWrap};
 use hora::core::ann_index::ANNIndex;
 use ndarray::{Array, CowArray, Ix1};
 use rocket::serde::{json, Deserialize, Serialize};
-mod dbpedia;
+use sled::transaction::{TransactionError, Transactional};
+use std::collections::{HashMap, HashSet};
+use std::env::var;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs::File, io::BufReader};
+use uuid::Uuid;
+pub mod anchors;
+pub mod crawl;
+pub mod dbpedia;
+pub mod entities;
+pub mod index;
+pub mod lexical;
+pub mod pagerank;
+pub mod panel;
+pub mod robots;
+pub mod sitemap;
+pub mod sparql;
+pub mod spelling;
+pub mod suggest;
+pub mod weighting;
 
 #[derive(Serialize, Deserialize)]
 pub struct CrawledEntry {
@@ -12,123 +42,1045 @@ pub struct CrawledEntry {
     pub description: String,
     pub vec: Vec<f32>,
     pub language: String,
+    /// Readable text of the page, without navigation and boilerplate.
+    #[serde(default)]
+    pub body: String,
+    /// Vectors of the passages `body` is split into, see `split_passages`.
+    #[serde(default)]
+    pub passages: Vec<Vec<f32>>,
+    /// Links found on the page.
+    #[serde(default)]
+    pub links: Vec<Link>,
+    /// Texts other pages use when linking to this one.
+    #[serde(default)]
+    pub anchors: Vec<String>,
+    /// Seconds since the epoch the page was last fetched, zero if unknown.
+    #[serde(default)]
+    pub fetched_at: u64,
+    /// `ETag` header of the last fetch, sent back as `If-None-Match`.
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// `Last-Modified` header of the last fetch, sent back as `If-Modified-Since`.
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// Seconds after `fetched_at` the page is checked for changes again, see
+    /// `CrawledEntry::schedule_recrawl`.
+    #[serde(default)]
+    pub recrawl_interval: u64,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Bounds of `CrawledEntry::recrawl_interval`, and the interval of newly crawled pages.
+static MIN_RECRAWL_INTERVAL: u64 = 24 * 60 * 60;
+static MAX_RECRAWL_INTERVAL: u64 = 90 * 24 * 60 * 60;
+pub static DEFAULT_RECRAWL_INTERVAL: u64 = 7 * 24 * 60 * 60;
+
+/// Seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+impl CrawledEntry {
+    /// Whether the page is due to be checked for changes at `now`, in seconds since the epoch.
+    pub fn is_due(&self, now: u64) -> bool {
+        now.saturating_sub(self.fetched_at) >= self.recrawl_interval
+    }
+
+    /// Records a fetch at `now`. Pages that `changed` since the last fetch are checked twice
+    /// as often from then on, and pages that did not half as often.
+    pub fn schedule_recrawl(&mut self, now: u64, changed: bool) {
+        let interval = match self.recrawl_interval {
+            0 => DEFAULT_RECRAWL_INTERVAL,
+            interval if changed => interval / 2,
+            interval => interval * 2,
+        };
+        self.recrawl_interval = interval.clamp(MIN_RECRAWL_INTERVAL, MAX_RECRAWL_INTERVAL);
+        self.fetched_at = now;
+    }
+
+    /// Whether `other` holds the same text and links as this entry, whatever their fetch
+    /// details.
+    pub fn has_same_content(&self, other: &CrawledEntry) -> bool {
+        self.title == other.title
+            && self.header == other.header
+            && self.description == other.description
+            && self.language == other.language
+            && self.body == other.body
+            && self.links == other.links
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Link {
+    pub url: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Url {
+    /// Id the document is stored under, as `get_entry_id` gives it.
+    #[serde(default)]
+    pub id: String,
     pub url: String,
     pub title: String,
     pub header: String,
     pub description: String,
     pub language: String,
+    /// Fused relevance of the hit, higher is better.
     pub score: f32,
 }
 
+/// How the vector and the lexical rankings are combined into the final one.
+pub enum Fusion {
+    /// Scores of each ranking are min-max normalized and summed with these weights.
+    Weighted {
+        vector_weight: f32,
+        lexical_weight: f32,
+    },
+    /// Reciprocal rank fusion, every ranking adds `1 / (k + rank)` to a hit.
+    ReciprocalRank { k: f32 },
+}
+
+/// Settings that turn candidate hits into the final ranking.
+pub struct Ranking {
+    pub fusion: Fusion,
+    /// Share of the final score taken by the PageRank of a page, between 0 and 1. The rest is
+    /// its relevance to the query.
+    pub authority_weight: f32,
+}
+
+/// Source of word vectors used to embed documents and queries.
+pub trait Embedder: Send + Sync {
+    /// Length of the vectors returned by `word_embedding`.
+    fn dimension(&self) -> usize;
+
+    fn word_embedding<'a>(&'a self, word: &str) -> Option<CowArray<'a, f32, Ix1>>;
+
+    /// Words the model has vectors for, most frequent first.
+    fn vocabulary(&self) -> &[String];
+}
+
+impl<V, S> Embedder for Embeddings<V, S>
+where
+    V: Vocab + Send + Sync,
+    S: Storage + Send + Sync,
+{
+    fn dimension(&self) -> usize {
+        self.dims()
+    }
+
+    fn word_embedding<'a>(&'a self, word: &str) -> Option<CowArray<'a, f32, Ix1>> {
+        self.embedding(word)
+    }
+
+    fn vocabulary(&self) -> &[String] {
+        self.vocab().words()
+    }
+}
+
+/// On-disk formats an `Embedder` can be loaded from.
+#[derive(Clone, Copy)]
+pub enum EmbeddingsFormat {
+    /// GloVe style text, one word and its vector per line.
+    Text,
+    /// Text with a leading `<words> <dimension>` line.
+    TextDims,
+    /// word2vec binary.
+    Word2Vec,
+    /// finalfusion binary, read into memory.
+    Finalfusion,
+    /// finalfusion binary, memory mapped.
+    FinalfusionMmap,
+}
+
+impl FromStr for EmbeddingsFormat {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(Self::Text),
+            "textdims" => Ok(Self::TextDims),
+            "word2vec" => Ok(Self::Word2Vec),
+            "finalfusion" => Ok(Self::Finalfusion),
+            "finalfusion-mmap" => Ok(Self::FinalfusionMmap),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn load_embedder(
+    path: &Path,
+    format: EmbeddingsFormat,
+) -> Result<Box<dyn Embedder>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    Ok(match format {
+        EmbeddingsFormat::Text => {
+            Box::new(Embeddings::<SimpleVocab, NdArray>::read_text(&mut reader)?)
+        }
+        EmbeddingsFormat::TextDims => Box::new(Embeddings::<SimpleVocab, NdArray>::read_text_dims(
+            &mut reader,
+        )?),
+        EmbeddingsFormat::Word2Vec => Box::new(
+            Embeddings::<SimpleVocab, NdArray>::read_word2vec_binary(&mut reader)?,
+        ),
+        EmbeddingsFormat::Finalfusion => Box::new(
+            Embeddings::<VocabWrap, StorageWrap>::read_embeddings(&mut reader)?,
+        ),
+        EmbeddingsFormat::FinalfusionMmap => Box::new(
+            Embeddings::<VocabWrap, StorageWrap>::mmap_embeddings(&mut reader)?,
+        ),
+    })
+}
+
+/// Loads the model at `EMBEDDINGS_PATH` in the `EMBEDDINGS_FORMAT` format, defaulting to the
+/// 50 dimensional GloVe vectors fetched by `bootstrap.sh`.
+pub fn load_embedder_from_env(
+) -> Result<Box<dyn Embedder>, Box<dyn std::error::Error + Send + Sync>> {
+    let path = match var("EMBEDDINGS_PATH") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => {
+            let mut p = project_root::get_project_root()?;
+            p.push("glove.6B/glove.6B.50d.txt");
+            p
+        }
+    };
+    let format = match var("EMBEDDINGS_FORMAT") {
+        Ok(name) => match name.parse() {
+            Ok(format) => format,
+            Err(_) => return Err(format!("Unknown embeddings format {}", name))?,
+        },
+        Err(_) => EmbeddingsFormat::Text,
+    };
+
+    load_embedder(&path, format)
+}
+
+static PASSAGES_TREE: &str = "passages";
+/// What a search result shows of each entry, kept apart so results are rendered without
+/// decoding the body and vectors of the entry.
+static RESULTS_TREE: &str = "results";
+static PASSAGE_WORDS: usize = 100;
+static MAX_PASSAGES: usize = 32;
+
+/// Key under which the entry for `url` is stored in the url database and the vector index.
+pub fn get_entry_id(url: &str) -> u128 {
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, url.as_bytes()).as_u128()
+}
+
+/// Entry id given either as the number it is stored under or as the UUID it is built from.
+pub fn parse_entry_id(id: &str) -> Option<u128> {
+    match id.parse::<u128>() {
+        Ok(id) => Some(id),
+        Err(_) => Uuid::parse_str(id).ok().map(|id| id.as_u128()),
+    }
+}
+
+/// Key under which the vector of the `index`th passage of `url` is stored in the vector index.
+pub fn get_passage_id(url: &str, index: usize) -> u128 {
+    get_entry_id(&format!("{}#passage-{}", url, index))
+}
+
+/// Fields of a `CrawledEntry` shown in search results.
+#[derive(Serialize, Deserialize)]
+struct ResultRecord {
+    url: String,
+    title: String,
+    header: String,
+    description: String,
+    language: String,
+    /// `CrawledEntry::fetched_at`, so the crawler reads it without decoding the entry.
+    #[serde(default)]
+    fetched_at: u64,
+}
+
+impl ResultRecord {
+    fn new(entry: &CrawledEntry) -> Self {
+        Self {
+            url: entry.url.clone(),
+            title: entry.title.clone(),
+            header: entry.header.clone(),
+            description: entry.description.clone(),
+            language: entry.language.clone(),
+            fetched_at: entry.fetched_at,
+        }
+    }
+}
+
+fn get_transaction_error(e: TransactionError<()>) -> sled::Error {
+    match e {
+        TransactionError::Storage(e) => e,
+        TransactionError::Abort(_) => sled::Error::Unsupported(String::from("aborted")),
+    }
+}
+
+/// Splits `text` into passages of at most `PASSAGE_WORDS` words, keeping the first
+/// `MAX_PASSAGES` of them.
+pub fn split_passages(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .collect::<Vec<&str>>()
+        .chunks(PASSAGE_WORDS)
+        .take(MAX_PASSAGES)
+        .map(|words| words.join(" "))
+        .collect()
+}
+
+/// Stores `entry` in the url database along with its terms, outgoing links and the passage to
+/// document mapping, and records its link texts as anchor texts of the pages they point to.
+/// A previous version of the entry is replaced in place, so it never goes missing from search.
+pub fn store_entry(url_db: &sled::Db, entry: &CrawledEntry) -> sled::Result<u128> {
+    let url_key = get_entry_id(&entry.url);
+    let previous_passages = match get_entry(url_db, url_key)? {
+        Some(previous) => previous.passages.len(),
+        None => 0,
+    };
+
+    let entries: &sled::Tree = url_db;
+    let results = url_db.open_tree(RESULTS_TREE)?;
+    let passages = url_db.open_tree(PASSAGES_TREE)?;
+    let key = url_key.to_string();
+    let value = json::to_string(entry).unwrap();
+    let record = json::to_string(&ResultRecord::new(entry)).unwrap();
+    (entries, &results, &passages)
+        .transaction(|(entries, results, passages)| {
+            for index in 0..entry.passages.len() {
+                passages.insert(
+                    get_passage_id(&entry.url, index).to_string().as_str(),
+                    key.as_str(),
+                )?;
+            }
+            for index in entry.passages.len()..previous_passages {
+                passages.remove(get_passage_id(&entry.url, index).to_string().as_str())?;
+            }
+            results.insert(key.as_str(), record.as_str())?;
+            entries.insert(key.as_str(), value.as_str())?;
+            Ok(())
+        })
+        .map_err(get_transaction_error)?;
+
+    let mut targets: Vec<u128> = entry
+        .links
+        .iter()
+        .map(|link| get_entry_id(&link.url))
+        .collect();
+    targets.sort();
+    targets.dedup();
+    pagerank::store_links(url_db, url_key, &targets)?;
+    lexical::index_document(url_db, url_key, entry)?;
+
+    for target in anchors::set_anchor_texts(url_db, url_key, &get_anchor_links(entry))? {
+        refresh_anchor_texts(url_db, target)?;
+    }
+
+    Ok(url_key)
+}
+
+/// Targets and texts of the links of `entry`.
+fn get_anchor_links(entry: &CrawledEntry) -> Vec<(u128, String)> {
+    entry
+        .links
+        .iter()
+        .map(|link| (get_entry_id(&link.url), link.text.clone()))
+        .collect()
+}
+
+/// Overwrites the stored `entry` without indexing it again, for changes to its fetch details
+/// that leave its text and vectors as they were.
+pub fn update_entry(url_db: &sled::Db, entry: &CrawledEntry) -> sled::Result<()> {
+    let entries: &sled::Tree = url_db;
+    let results = url_db.open_tree(RESULTS_TREE)?;
+    let key = get_entry_id(&entry.url).to_string();
+    let value = json::to_string(entry).unwrap();
+    let record = json::to_string(&ResultRecord::new(entry)).unwrap();
+    (entries, &results)
+        .transaction(|(entries, results)| {
+            results.insert(key.as_str(), record.as_str())?;
+            entries.insert(key.as_str(), value.as_str())?;
+            Ok(())
+        })
+        .map_err(get_transaction_error)
+}
+
+/// Brings the anchor texts of an already stored entry up to date, so they are searchable
+/// before the page is crawled or embedded again.
+fn refresh_anchor_texts(url_db: &sled::Db, url_key: u128) -> sled::Result<()> {
+    let mut entry = match url_db.get(url_key.to_string())? {
+        Some(value) => {
+            match json::from_str::<CrawledEntry>(String::from_utf8_lossy(&value).as_ref()) {
+                Ok(entry) => entry,
+                Err(_) => return Ok(()),
+            }
+        }
+        None => return Ok(()),
+    };
+
+    entry.anchors = anchors::get_anchor_texts(url_db, url_key)?;
+    url_db.insert(
+        url_key.to_string(),
+        json::to_string(&entry).unwrap().as_str(),
+    )?;
+    lexical::index_document(url_db, url_key, &entry)
+}
+
+/// Moves anchor texts stored before they were kept per linking page over to the current
+/// layout, rebuilding them from the links of the stored entries.
+pub fn ensure_anchor_texts(url_db: &sled::Db) -> sled::Result<()> {
+    anchors::migrate_legacy_anchors(
+        url_db,
+        url_db.iter().filter_map(|url| {
+            let (key, value) = url.ok()?;
+            let entry =
+                json::from_str::<CrawledEntry>(String::from_utf8_lossy(&value).as_ref()).ok()?;
+            let source = String::from_utf8_lossy(&key).parse().ok()?;
+            Some((source, get_anchor_links(&entry)))
+        }),
+    )
+}
+
+/// Writes the search result records of entries stored before they were kept, so every entry
+/// has one.
+pub fn ensure_result_records(url_db: &sled::Db) -> sled::Result<()> {
+    let results = url_db.open_tree(RESULTS_TREE)?;
+    if results.len() >= url_db.len() {
+        return Ok(());
+    }
+
+    for url in url_db.iter() {
+        let (key, value) = url?;
+        if results.contains_key(&key)? {
+            continue;
+        }
+        if let Ok(entry) = json::from_str::<CrawledEntry>(String::from_utf8_lossy(&value).as_ref())
+        {
+            results.insert(
+                key,
+                json::to_string(&ResultRecord::new(&entry))
+                    .unwrap()
+                    .as_str(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Entry stored under `url_key`, if any.
+pub fn get_entry(url_db: &sled::Db, url_key: u128) -> sled::Result<Option<CrawledEntry>> {
+    Ok(match url_db.get(url_key.to_string())? {
+        Some(value) => {
+            json::from_str::<CrawledEntry>(String::from_utf8_lossy(&value).as_ref()).ok()
+        }
+        None => None,
+    })
+}
+
+/// Removes the entry stored under `url_key`, returning it if there was one.
+pub fn remove_entry(url_db: &sled::Db, url_key: u128) -> sled::Result<Option<CrawledEntry>> {
+    let entry = match url_db.get(url_key.to_string())? {
+        Some(value) => {
+            json::from_str::<CrawledEntry>(String::from_utf8_lossy(&value).as_ref()).ok()
+        }
+        None => return Ok(None),
+    };
+
+    let entries: &sled::Tree = url_db;
+    let results = url_db.open_tree(RESULTS_TREE)?;
+    let passages = url_db.open_tree(PASSAGES_TREE)?;
+    let key = url_key.to_string();
+    (entries, &results, &passages)
+        .transaction(|(entries, results, passages)| {
+            if let Some(entry) = &entry {
+                for index in 0..entry.passages.len() {
+                    passages.remove(get_passage_id(&entry.url, index).to_string().as_str())?;
+                }
+            }
+            results.remove(key.as_str())?;
+            entries.remove(key.as_str())?;
+            Ok(())
+        })
+        .map_err(get_transaction_error)?;
+
+    lexical::remove_document(url_db, url_key)?;
+    pagerank::remove_links(url_db, url_key)?;
+    for target in anchors::set_anchor_texts(url_db, url_key, &[])? {
+        refresh_anchor_texts(url_db, target)?;
+    }
+
+    Ok(entry)
+}
+
+/// Resolves a vector index id to the id of the document it belongs to.
+fn get_document_id(url_db: &sled::Db, vec_id: u128) -> u128 {
+    let parent = url_db
+        .open_tree(PASSAGES_TREE)
+        .and_then(|passages| passages.get(vec_id.to_string()));
+
+    match parent {
+        Ok(Some(value)) => String::from_utf8_lossy(&value).parse().unwrap_or(vec_id),
+        _ => vec_id,
+    }
+}
+
 pub fn get_word_embedding<'a>(
-    embeddings: &'a Embeddings<SimpleVocab, NdArray>,
+    embeddings: &'a dyn Embedder,
     word: &'a str,
 ) -> Option<CowArray<'a, f32, Ix1>> {
-    return embeddings.embedding(word.to_lowercase().as_ref());
+    return embeddings.word_embedding(word.to_lowercase().as_ref());
 }
 
-pub fn get_chunk_embedding(
-    embeddings: &Embeddings<SimpleVocab, NdArray>,
+/// Divides a weighted sum of word vectors by the total weight. The plain mean keeps dividing by
+/// every word of the sentence, in or out of the vocabulary.
+fn get_weighted_mean(
+    sum_vector: Array<f32, Ix1>,
+    total_weight: f32,
+    word_count: usize,
+    weighting: &Weighting,
+) -> Option<Array<f32, Ix1>> {
+    if (sum_vector.sum()) == 0.0 {
+        return None;
+    }
+
+    match weighting.mode {
+        WeightingMode::Mean => Some(sum_vector / (word_count as f32)),
+        _ => Some(sum_vector / total_weight),
+    }
+}
+
+fn get_chunk_mean(
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
     sentence: &str,
 ) -> Option<Array<f32, Ix1>> {
     let words: Vec<&str> = sentence.split_whitespace().collect();
 
-    let mut sum_vector = Array::<f32, Ix1>::zeros(50);
+    let mut sum_vector = Array::<f32, Ix1>::zeros(embeddings.dimension());
+    let mut total_weight = 0.0;
     for word in &words {
-        match get_word_embedding(&embeddings, word) {
-            Some(embedding) => sum_vector = sum_vector + embedding,
+        match get_word_embedding(embeddings, word) {
+            Some(embedding) => {
+                let weight = weighting.word_weight(word);
+                sum_vector = sum_vector + &embedding * weight;
+                total_weight += weight;
+            }
             None => (),
         }
     }
 
-    if (sum_vector.sum()) == 0.0 {
-        return None;
-    } else {
-        return Some(sum_vector / (words.len() as f32));
-    }
+    get_weighted_mean(sum_vector, total_weight, words.len(), weighting)
+}
+
+pub fn get_chunk_embedding(
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
+    sentence: &str,
+) -> Option<Array<f32, Ix1>> {
+    get_chunk_mean(embeddings, weighting, sentence)
+        .map(|vector| weighting.remove_common_component(vector))
 }
 
+/// Embeds `sentence`, standing in the summary of each of its linked entities for the words
+/// naming it.
 pub async fn get_sentence_embedding(
-    client: &reqwest::Client,
-    embeddings: &Embeddings<SimpleVocab, NdArray>,
+    dbpedia: &Dbpedia,
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
     sentence: &str,
+    language: &str,
+) -> Option<Array<f32, Ix1>> {
+    let entities = link_entities(dbpedia, embeddings, weighting, sentence, language).await;
+    get_linked_sentence_embedding(
+        dbpedia, embeddings, weighting, sentence, language, &entities,
+    )
+    .await
+}
+
+/// `get_sentence_embedding` with the entities of `sentence` already linked.
+pub async fn get_linked_sentence_embedding(
+    dbpedia: &Dbpedia,
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
+    sentence: &str,
+    language: &str,
+    entities: &[Entity],
 ) -> Option<Array<f32, Ix1>> {
     let words: Vec<&str> = sentence.split_whitespace().collect();
 
-    let mut sum_vector = Array::<f32, Ix1>::zeros(50);
-    for word in &words {
-        match get_word_embedding(embeddings, word) {
-            Some(embedding) => sum_vector = sum_vector + embedding,
-            None => {
-                if let Ok(dbpedia_resource) = dbpedia::get_resource(&client, word).await {
-                    if let Ok(dbpedia_summary) =
-                        dbpedia::get_summary(&client, &dbpedia_resource).await
-                    {
-                        if let Some(embedding) = get_chunk_embedding(embeddings, &dbpedia_summary) {
-                            sum_vector = sum_vector + embedding;
-                        }
-                    }
-                }
+    let mut sum_vector = Array::<f32, Ix1>::zeros(embeddings.dimension());
+    let mut total_weight = 0.0;
+    let mut position = 0;
+    while position < words.len() {
+        if let Some(entity) = entities.iter().find(|entity| entity.start == position) {
+            let weight: f32 = words[entity.start..entity.end]
+                .iter()
+                .map(|word| weighting.word_weight(word))
+                .sum();
+            let summary_embedding = match dbpedia.get_summary(&entity.resource, language).await {
+                Ok(summary) => get_chunk_mean(embeddings, weighting, &summary),
+                Err(_) => None,
+            };
+
+            if let Some(embedding) = summary_embedding {
+                sum_vector = sum_vector + embedding * weight;
+                total_weight += weight;
+                position = entity.end;
+                continue;
             }
         }
+
+        let word = words[position];
+        if let Some(embedding) = get_word_embedding(embeddings, word) {
+            let weight = weighting.word_weight(word);
+            sum_vector = sum_vector + &embedding * weight;
+            total_weight += weight;
+        }
+        position += 1;
     }
 
-    if (sum_vector.sum()) == 0.0 {
-        return None;
-    } else {
-        return Some(sum_vector / (words.len() as f32));
+    get_weighted_mean(sum_vector, total_weight, words.len(), weighting)
+        .map(|vector| weighting.remove_common_component(vector))
+}
+
+/// Computes the vector stored in the index for `entry`, from its title and the anchor texts
+/// pointing to it.
+pub async fn get_entry_embedding(
+    dbpedia: &Dbpedia,
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
+    entry: &CrawledEntry,
+) -> Option<Array<f32, Ix1>> {
+    let title_vector = get_sentence_embedding(
+        dbpedia,
+        embeddings,
+        weighting,
+        &entry.title,
+        &entry.language,
+    )
+    .await;
+    let anchors_vector = get_chunk_embedding(embeddings, weighting, &entry.anchors.join(" "));
+
+    match (title_vector, anchors_vector) {
+        (Some(title_vector), Some(anchors_vector)) => Some((title_vector + anchors_vector) / 2.0),
+        (title_vector, anchors_vector) => title_vector.or(anchors_vector),
     }
 }
 
-pub async fn get_url_list(
-    client: &reqwest::Client,
-    embeddings: &Embeddings<SimpleVocab, NdArray>,
+/// Computes the vectors of the passages of `entry`, skipping the ones with no known word so
+/// that passage indexes stay aligned with `split_passages`.
+pub fn get_passage_embeddings(
+    embeddings: &dyn Embedder,
+    weighting: &Weighting,
+    entry: &CrawledEntry,
+) -> Vec<Vec<f32>> {
+    split_passages(&entry.body)
+        .iter()
+        .map(
+            |passage| match get_chunk_embedding(embeddings, weighting, passage) {
+                Some(vector) => vector.to_vec(),
+                None => vec![],
+            },
+        )
+        .collect()
+}
+
+/// Id and title of every stored entry, read from the search result records.
+pub fn get_result_titles(url_db: &sled::Db) -> sled::Result<impl Iterator<Item = (u128, String)>> {
+    Ok(url_db
+        .open_tree(RESULTS_TREE)?
+        .iter()
+        .filter_map(|result| result.ok())
+        .filter_map(|(key, value)| {
+            let id = String::from_utf8_lossy(&key).parse().ok()?;
+            let record =
+                json::from_str::<ResultRecord>(String::from_utf8_lossy(&value).as_ref()).ok()?;
+            Some((id, record.title))
+        }))
+}
+
+/// Seconds since the epoch the entry stored under `id` was last fetched, if it is stored and
+/// the time is known, read from its search result record.
+pub fn get_fetched_at(url_db: &sled::Db, id: u128) -> sled::Result<Option<u64>> {
+    Ok(match url_db.open_tree(RESULTS_TREE)?.get(id.to_string())? {
+        Some(value) => json::from_str::<ResultRecord>(String::from_utf8_lossy(&value).as_ref())
+            .ok()
+            .map(|record| record.fetched_at)
+            .filter(|fetched_at| *fetched_at > 0),
+        None => None,
+    })
+}
+
+/// Search result record of the entry stored under `id`, read from the full entry for entries
+/// stored before records were kept.
+fn get_result_record(url_db: &sled::Db, id: u128) -> Result<Option<ResultRecord>, ()> {
+    let key = id.to_string();
+    let record = url_db
+        .open_tree(RESULTS_TREE)
+        .and_then(|results| results.get(&key))
+        .map_err(|_| ())?;
+    match record {
+        Some(value) => json::from_str::<ResultRecord>(String::from_utf8_lossy(&value).as_ref())
+            .map(Some)
+            .map_err(|_| ()),
+        None => match url_db.get(&key) {
+            Ok(Some(value)) => {
+                json::from_str::<CrawledEntry>(String::from_utf8_lossy(&value).as_ref())
+                    .map(|entry| Some(ResultRecord::new(&entry)))
+                    .map_err(|_| ())
+            }
+            _ => Ok(None),
+        },
+    }
+}
+
+fn get_url(
+    url_db: &sled::Db,
+    id: u128,
+    score: f32,
+    language_option: Option<&str>,
+) -> Result<Option<Url>, ()> {
+    let url_value = match get_result_record(url_db, id)? {
+        Some(url_value) => url_value,
+        None => return Ok(None),
+    };
+    if let Some(language) = language_option {
+        if !url_value.language.eq(language) {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(Url {
+        id: id.to_string(),
+        url: url_value.url,
+        title: url_value.title,
+        header: url_value.header,
+        description: url_value.description,
+        language: url_value.language,
+        score,
+    }))
+}
+
+/// Looks up the `candidates` nearest vectors to `query_vec`, dropping the documents that do not
+/// match `language_option` and the `excluded` one. Also returns how many nodes the index
+/// yielded before filtering.
+fn search_url_candidates(
     vec_index: &hora::index::hnsw_idx::HNSWIndex<f32, u128>,
     url_db: &sled::Db,
+    query_vec: &[f32],
+    candidates: usize,
+    language_option: Option<&str>,
+    excluded: Option<u128>,
+) -> Result<(Vec<Url>, usize), ()> {
+    let nodes = vec_index.search_nodes(query_vec, candidates);
+    let found = nodes.len();
+
+    // Passage hits are grouped back into their document, which keeps its best scored hit.
+    let mut seen: HashSet<u128> = HashSet::new();
+    let mut urls: Vec<Url> = Vec::new();
+    for node in nodes {
+        if let Some(vec_id) = node.0.idx() {
+            let document_id = get_document_id(url_db, *vec_id);
+            if Some(document_id) == excluded || !seen.insert(document_id) {
+                continue;
+            }
+            if let Some(url) = get_url(url_db, document_id, node.1, language_option)? {
+                urls.push(url);
+            }
+        }
+    }
+
+    Ok((urls, found))
+}
+
+async fn get_vector_url_list(
+    context: &SearchContext<'_>,
     query: &str,
+    query_language: &str,
+    wanted: usize,
+    language_option: Option<&str>,
+    entities: &[Entity],
+) -> Result<Vec<Url>, ()> {
+    match get_linked_sentence_embedding(
+        context.dbpedia,
+        context.embeddings,
+        context.weighting,
+        query,
+        query_language,
+        entities,
+    )
+    .await
+    {
+        Some(query_vec) => search_url_list(
+            context.vec_index,
+            context.url_db,
+            &query_vec.to_vec(),
+            wanted,
+            language_option,
+            None,
+        ),
+        None => Ok(vec![]),
+    }
+}
+
+/// The `wanted` documents nearest to `query_vec`, see `search_url_candidates`.
+fn search_url_list(
+    vec_index: &hora::index::hnsw_idx::HNSWIndex<f32, u128>,
+    url_db: &sled::Db,
+    query_vec: &[f32],
+    wanted: usize,
+    language_option: Option<&str>,
+    excluded: Option<u128>,
+) -> Result<Vec<Url>, ()> {
+    // Filtered out hits would leave the page short, so the candidate pool keeps growing
+    // until it holds enough matching entries or the index runs out of nodes.
+    let mut urls: Vec<Url>;
+    let mut candidates = wanted;
+    loop {
+        let (filtered, found) = search_url_candidates(
+            vec_index,
+            url_db,
+            query_vec,
+            candidates,
+            language_option,
+            excluded,
+        )?;
+        urls = filtered;
+
+        if urls.len() >= wanted || found < candidates {
+            break;
+        }
+        candidates *= 2;
+    }
+
+    urls.truncate(wanted);
+    Ok(urls)
+}
+
+/// The `page`th page of the documents nearest to the one stored under `url_key`, itself
+/// excluded, or `None` if there is no such document. Documents whose vector is not of the
+/// index `dimension` have no neighbors.
+pub fn get_similar_url_list(
+    vec_index: &hora::index::hnsw_idx::HNSWIndex<f32, u128>,
+    url_db: &sled::Db,
+    dimension: usize,
+    url_key: u128,
     page: usize,
     page_size: usize,
     language_option: Option<&str>,
+) -> Result<Option<Vec<Url>>, ()> {
+    let entry = match get_entry(url_db, url_key).map_err(|_| ())? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    if entry.vec.len() != dimension {
+        return Ok(Some(vec![]));
+    }
+
+    let urls = search_url_list(
+        vec_index,
+        url_db,
+        &entry.vec,
+        page_size.saturating_mul(page.max(1)),
+        language_option,
+        Some(url_key),
+    )?;
+    Ok(Some(
+        urls.into_iter()
+            .skip(page_size.saturating_mul(page.saturating_sub(1)))
+            .collect(),
+    ))
+}
+
+fn get_lexical_url_list(
+    url_db: &sled::Db,
+    query: &str,
+    wanted: usize,
+    language_option: Option<&str>,
 ) -> Result<Vec<Url>, ()> {
-    let mut urls: Vec<Url> = Vec::new();
-    if let Some(query_vec) = get_sentence_embedding(client, embeddings, query).await {
-        for node in vec_index
-            .search_nodes(&query_vec.to_vec(), page_size * page)
-            .split_off(page_size * (page - 1))
-        {
-            if let Some(vec_id) = node.0.idx() {
-                if let Ok(value_result) = url_db.get(&vec_id.to_string()) {
-                    if let Some(value_option) = value_result {
-                        match json::from_str::<CrawledEntry>(
-                            String::from_utf8_lossy(&value_option).as_ref(),
-                        ) {
-                            Ok(url_value) => {
-                                if let Some(language) = language_option {
-                                    if !url_value.language.eq(language) {
-                                        continue;
-                                    }
-                                }
-
-                                urls.push(Url {
-                                    url: url_value.url,
-                                    title: url_value.title,
-                                    header: url_value.header,
-                                    description: url_value.description,
-                                    language: url_value.language,
-                                    score: node.1,
-                                });
-                            }
-                            Err(_) => return Err(()),
-                        }
-                    }
+    // As with vector hits, the pool grows until enough hits match the language.
+    let mut candidates = wanted;
+    loop {
+        let scores = lexical::search_bm25(url_db, query, candidates).map_err(|_| ())?;
+        let found = scores.len();
+
+        let mut urls: Vec<Url> = Vec::new();
+        for (id, score) in scores {
+            if urls.len() >= wanted {
+                break;
+            }
+            if let Some(url) = get_url(url_db, id, score, language_option)? {
+                urls.push(url);
+            }
+        }
+
+        if urls.len() >= wanted || found < candidates {
+            return Ok(urls);
+        }
+        candidates *= 2;
+    }
+}
+
+/// Normalizes the scores of a best-first ranking to `[0, 1]`, whatever their direction.
+fn get_normalized_scores(urls: &[Url]) -> Vec<f32> {
+    match (urls.first(), urls.last()) {
+        (Some(best), Some(worst)) if best.score != worst.score => urls
+            .iter()
+            .map(|url| (url.score - worst.score) / (best.score - worst.score))
+            .collect(),
+        _ => vec![1.0; urls.len()],
+    }
+}
+
+/// Combines best-first rankings into one, scored so that higher is better.
+pub fn fuse_url_lists(rankings: Vec<(Vec<Url>, f32)>, fusion: &Fusion) -> Vec<Url> {
+    let mut fused: HashMap<String, Url> = HashMap::new();
+    for (urls, weight) in rankings {
+        let scores: Vec<f32> = match fusion {
+            Fusion::Weighted { .. } => get_normalized_scores(&urls)
+                .into_iter()
+                .map(|score| score * weight)
+                .collect(),
+            Fusion::ReciprocalRank { k } => (0..urls.len())
+                .map(|rank| 1.0 / (k + rank as f32 + 1.0))
+                .collect(),
+        };
+
+        for (mut url, score) in urls.into_iter().zip(scores) {
+            match fused.get_mut(&url.url) {
+                Some(existing) => existing.score += score,
+                None => {
+                    url.score = score;
+                    fused.insert(url.url.clone(), url);
                 }
             }
         }
     }
 
-    Ok(urls)
+    let mut urls: Vec<Url> = fused.into_values().collect();
+    urls.sort_by(|a, b| b.score.total_cmp(&a.score));
+    urls
+}
+
+/// Deepest page of results served, as every page before it is ranked to cut it out.
+pub static MAX_PAGE: usize = 100;
+/// Hits on a page of results.
+pub static PAGE_SIZE: usize = 5;
+
+/// Hits ranked together before a page is cut out of them, enough for `MAX_PAGE` pages of
+/// `PAGE_SIZE`. Fusion and authority blending are relative to the hits they are given, so
+/// every page of a query is cut from one pool of the same size.
+static RANKING_POOL: usize = 500;
+
+/// Mixes the relevance of every hit, relative to the best one, with the PageRank of its page.
+fn blend_authority(url_db: &sled::Db, urls: Vec<Url>, authority_weight: f32) -> Vec<Url> {
+    if authority_weight <= 0.0 {
+        return urls;
+    }
+
+    let best = urls.iter().map(|url| url.score).fold(0.0, f32::max);
+    let mut urls: Vec<Url> = urls
+        .into_iter()
+        .map(|mut url| {
+            let relevance = if best > 0.0 { url.score / best } else { 0.0 };
+            let authority = pagerank::get_pagerank(url_db, get_entry_id(&url.url));
+            url.score = (1.0 - authority_weight) * relevance + authority_weight * authority;
+            url
+        })
+        .collect();
+
+    urls.sort_by(|a, b| b.score.total_cmp(&a.score));
+    urls
+}
+
+/// What a search reads from: the knowledge base, the embeddings, the indexes and the ranking
+/// settings.
+pub struct SearchContext<'a> {
+    pub dbpedia: &'a Dbpedia,
+    pub embeddings: &'a dyn Embedder,
+    pub weighting: &'a Weighting,
+    pub vec_index: &'a hora::index::hnsw_idx::HNSWIndex<f32, u128>,
+    pub url_db: &'a sled::Db,
+    pub ranking: &'a Ranking,
+    /// Speller correcting queries none of whose words are known, if any.
+    pub speller: Option<&'a Speller>,
+}
+
+/// A page of the urls best matching `query`, along with the entities linked in the query.
+pub async fn get_url_list(
+    context: &SearchContext<'_>,
+    query: &str,
+    query_language: &str,
+    page: usize,
+    page_size: usize,
+    language_option: Option<&str>,
+) -> Result<(Vec<Url>, Vec<Entity>), ()> {
+    let SearchContext {
+        dbpedia,
+        embeddings,
+        weighting,
+        url_db,
+        ranking,
+        speller,
+        ..
+    } = *context;
+    let wanted = page_size.saturating_mul(page);
+    let pool = RANKING_POOL.max(wanted);
+
+    // A query without a single word of the vocabulary is most likely misspelled, so it is
+    // searched as corrected when a speller is given.
+    let corrected_query: String;
+    let query = match speller {
+        Some(speller)
+            if query
+                .split_whitespace()
+                .all(|word| get_word_embedding(embeddings, word).is_none()) =>
+        {
+            corrected_query = speller.correct(query);
+            corrected_query.as_str()
+        }
+        _ => query,
+    };
+
+    let entities = link_entities(dbpedia, embeddings, weighting, query, query_language).await;
+    let vector_urls = get_vector_url_list(
+        context,
+        query,
+        query_language,
+        pool,
+        language_option,
+        &entities,
+    )
+    .await?;
+    let lexical_urls = get_lexical_url_list(url_db, query, pool, language_option)?;
+
+    let (vector_weight, lexical_weight) = match &ranking.fusion {
+        Fusion::Weighted {
+            vector_weight,
+            lexical_weight,
+        } => (*vector_weight, *lexical_weight),
+        Fusion::ReciprocalRank { .. } => (1.0, 1.0),
+    };
+    let urls = fuse_url_lists(
+        vec![(vector_urls, vector_weight), (lexical_urls, lexical_weight)],
+        &ranking.fusion,
+    );
+    let urls = blend_authority(url_db, urls, ranking.authority_weight);
+
+    let urls = urls
+        .into_iter()
+        .skip(page_size.saturating_mul(page.saturating_sub(1)))
+        .take(page_size)
+        .collect();
+    Ok((urls, entities))
+}
+
+/// Rank constant of the reciprocal rank fusion that merges the rankings of several nodes.
+static MERGE_RANK_K: f32 = 60.0;
+
+/// Merges best-first url lists coming from several nodes by reciprocal rank fusion, as nodes
+/// score hits against their own corpus and settings, so their scores are not comparable.
+pub fn merge_url_lists(url_lists: Vec<Vec<Url>>) -> Vec<Url> {
+    fuse_url_lists(
+        url_lists.into_iter().map(|urls| (urls, 1.0)).collect(),
+        &Fusion::ReciprocalRank { k: MERGE_RANK_K },
+    )
 }
@@ -1,18 +1,34 @@
 #[macro_use]
 extern crate rocket;
-use finalfusion::{
-    compat::text::ReadText, embeddings::Embeddings, storage::NdArray, vocab::SimpleVocab,
-};
-use hora::core::ann_index::ANNIndex;
+use lingua::Language::{English, Spanish};
+use lingua::{LanguageDetector, LanguageDetectorBuilder};
 use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome};
 use rocket::response::{self, Responder};
 use rocket::serde::{json, json::Json, Deserialize, Serialize};
 use rocket::{Request, State};
 use std::env::var;
-use std::{fs::File, io::BufReader};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use tree::{get_url_list, CrawledEntry, Url};
-mod dbpedia;
+use tree::anchors::get_anchor_texts;
+use tree::dbpedia::{load_dbpedia_from_env, Dbpedia, DEFAULT_LANGUAGE};
+use tree::entities::{get_main_entity, link_entities, resolve_entity, Entity};
+use tree::index::{load_or_build_index, watch_index, LiveIndex};
+use tree::lexical::ensure_lexical_index;
+use tree::panel::{
+    get_knowledge_panel, truncate_at_sentence, KnowledgePanel, PANEL_SUMMARY_LENGTH,
+};
+use tree::spelling::Speller;
+use tree::suggest::{Suggester, MAX_SUGGESTIONS};
+use tree::weighting::{load_weighting_from_env, Weighting};
+use tree::{
+    ensure_anchor_texts, ensure_result_records, get_chunk_embedding, get_entry,
+    get_entry_embedding, get_entry_id, get_passage_embeddings, get_result_titles,
+    get_similar_url_list, get_url_list, load_embedder_from_env, merge_url_lists, parse_entry_id,
+    remove_entry, store_entry, CrawledEntry, Embedder, Fusion, Ranking, SearchContext, Url,
+    MAX_PAGE, PAGE_SIZE,
+};
 
 #[derive(Serialize)]
 struct Answer {
@@ -20,6 +36,7 @@ struct Answer {
     urls: Vec<Url>,
     small_summary: String,
     corrected: String,
+    panel: Option<KnowledgePanel>,
 }
 
 #[derive(Serialize)]
@@ -28,6 +45,11 @@ struct Summary {
     corrected: String,
 }
 
+#[derive(Serialize)]
+struct Suggestions {
+    suggestions: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Results {
     urls: Vec<Url>,
@@ -44,11 +66,60 @@ struct Peer {
 }
 
 struct Config {
-    vec_index: hora::index::hnsw_idx::HNSWIndex<f32, u128>,
+    vec_index: Arc<LiveIndex>,
     db: sled::Db,
-    embeddings: Embeddings<SimpleVocab, NdArray>,
+    embeddings: Box<dyn Embedder>,
+    weighting: Weighting,
     peers: sled::Db,
     http_client: reqwest::Client,
+    dbpedia: Dbpedia,
+    this_peer: Peer,
+    peer_timeout: Duration,
+    ranking: Ranking,
+    speller: Speller,
+    suggester: Suggester,
+    search_corrected: bool,
+    detector: LanguageDetector,
+    /// Secret the requests to the ingest endpoints must carry, see `IngestAuthorized`.
+    ingest_token: Option<String>,
+    /// Whether requests from this machine may ingest without the secret.
+    ingest_trust_loopback: bool,
+}
+
+/// Header the ingest endpoints read `INGEST_TOKEN` from.
+static INGEST_TOKEN_HEADER: &str = "X-Ingest-Token";
+
+/// Guard of the endpoints that add and remove documents. Requests must carry the
+/// `INGEST_TOKEN` secret in the `X-Ingest-Token` header, or come from this machine when
+/// `INGEST_TRUST_LOOPBACK` is set. Behind a reverse proxy on the same machine every request
+/// comes from it, so that is only safe when the proxy does not expose the ingest endpoints.
+struct IngestAuthorized;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IngestAuthorized {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let state = match req.rocket().state::<Config>() {
+            Some(state) => state,
+            None => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        let has_token = match &state.ingest_token {
+            Some(token) => req.headers().get_one(INGEST_TOKEN_HEADER) == Some(token.as_str()),
+            None => false,
+        };
+        // The socket address is used rather than `client_ip`, which trusts `X-Real-IP`.
+        let authorized = has_token
+            || (state.ingest_trust_loopback
+                && req
+                    .remote()
+                    .map_or(false, |remote| remote.ip().is_loopback()));
+        match authorized {
+            true => Outcome::Success(IngestAuthorized),
+            false => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -93,87 +164,307 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
     }
 }
 
-#[get("/?<query>&<page>&<language_option>")]
+/// Best `count` hits of every peer for `query`.
+async fn get_peer_url_lists(
+    state: &Config,
+    query: &str,
+    count: usize,
+    language_option: Option<&str>,
+) -> Vec<Vec<Url>> {
+    let addresses: Vec<String> = state
+        .peers
+        .iter()
+        .filter_map(|peer| peer.ok())
+        .map(|peer| String::from_utf8_lossy(&peer.0).to_string())
+        .filter(|address| address != &state.this_peer.address)
+        .collect();
+
+    let requests = addresses.into_iter().map(|address| async move {
+        let mut params = vec![
+            ("query", query.to_string()),
+            ("page", String::from("1")),
+            ("page_size", count.to_string()),
+        ];
+        if let Some(language) = language_option {
+            params.push(("language_option", language.to_string()));
+        }
+
+        match state
+            .http_client
+            .get(format!("{}/_results", address))
+            .query(&params)
+            .timeout(state.peer_timeout)
+            .send()
+            .await
+        {
+            Ok(response) => match response.json::<Results>().await {
+                Ok(results) => results.urls,
+                Err(e) => {
+                    println!(
+                        "Error: {:?}. Deserialization error while fetching results from peer {}.",
+                        e, address
+                    );
+                    vec![]
+                }
+            },
+            Err(e) => {
+                println!(
+                    "Error: {:?}. Error fetching results from peer {}.",
+                    e, address
+                );
+                vec![]
+            }
+        }
+    });
+
+    futures::future::join_all(requests).await
+}
+
+/// Page of hits for `query`. Federated pages are cut from the merged rankings of this node
+/// and its peers, each asked for every hit up to the end of the page.
+async fn get_federated_url_list(
+    state: &Config,
+    query: &str,
+    page: usize,
+    page_size: usize,
+    language_option: Option<&str>,
+    federated: bool,
+) -> Result<(Vec<Url>, Vec<Entity>), Error> {
+    let wanted = page_size.saturating_mul(page.max(1));
+    let vec_index = state.vec_index.current();
+    let context = SearchContext {
+        dbpedia: &state.dbpedia,
+        embeddings: state.embeddings.as_ref(),
+        weighting: &state.weighting,
+        vec_index: &vec_index,
+        url_db: &state.db,
+        ranking: &state.ranking,
+        speller: if state.search_corrected {
+            Some(&state.speller)
+        } else {
+            None
+        },
+    };
+    let (urls, entities) = match get_url_list(
+        &context,
+        query,
+        &get_query_language(state, query, language_option),
+        if federated { 1 } else { page },
+        if federated { wanted } else { page_size },
+        language_option,
+    )
+    .await
+    {
+        Ok(results) => results,
+        Err(_) => return Err(Error::InternalServerError),
+    };
+
+    if !federated {
+        return Ok((urls, entities));
+    }
+
+    let mut url_lists = get_peer_url_lists(state, query, wanted, language_option).await;
+    url_lists.push(urls);
+
+    let urls = merge_url_lists(url_lists)
+        .into_iter()
+        .skip(wanted - page_size)
+        .take(page_size)
+        .collect();
+    Ok((urls, entities))
+}
+
+/// Language knowledge lookups for `query` are made in, the requested one or else the detected.
+fn get_query_language(state: &Config, query: &str, language_option: Option<&str>) -> String {
+    match language_option {
+        Some(language) => language.to_string(),
+        None => match state.detector.detect_language_of(query) {
+            Some(language) => language.iso_code_639_1().to_string(),
+            None => String::from(DEFAULT_LANGUAGE),
+        },
+    }
+}
+
+/// Resource the answer to `query` is about, the main of the `entities` linked in it or else the
+/// candidate for the whole query that best fits it.
+async fn get_answer_resource(
+    state: &Config,
+    query: &str,
+    language: &str,
+    entities: &[Entity],
+) -> String {
+    let embeddings = state.embeddings.as_ref();
+    if let Some(entity) = get_main_entity(entities) {
+        return entity.resource.clone();
+    }
+
+    let context = get_chunk_embedding(embeddings, &state.weighting, query);
+    match resolve_entity(
+        &state.dbpedia,
+        embeddings,
+        &state.weighting,
+        query,
+        language,
+        context.as_ref(),
+    )
+    .await
+    {
+        Some(resource) => resource,
+        None => String::from(""),
+    }
+}
+
+#[get("/?<query>&<page>&<language_option>&<federated>")]
 async fn _answer(
     state: &State<Config>,
     query: &str,
     page: usize,
     language_option: Option<&str>,
+    federated: Option<bool>,
 ) -> Result<Json<Answer>, Error> {
-    let page_size = 5;
+    let page_size = PAGE_SIZE;
+    if page > MAX_PAGE {
+        return Err(Error::BadRequest);
+    }
 
-    let urls = match get_url_list(
-        &state.http_client,
-        &state.embeddings,
-        &state.vec_index,
-        &state.db,
+    let (urls, entities) = get_federated_url_list(
+        state,
         query,
         page,
         page_size,
         language_option,
+        federated.unwrap_or(false),
     )
-    .await
-    {
-        Ok(results) => results,
-        Err(_) => return Err(Error::InternalServerError),
-    };
+    .await?;
 
-    let dbpedia_resource = dbpedia::get_resource(&state.http_client, query)
-        .await
-        .unwrap_or(String::from(""));
-    let answer = dbpedia::get_summary(&state.http_client, &dbpedia_resource)
+    if page <= 1 {
+        if let Err(e) = state.suggester.record_query(query) {
+            println!("Error: {:?}. Error recording query for suggestions.", e);
+        }
+    }
+
+    let language = get_query_language(state, query, language_option);
+    let dbpedia_resource = get_answer_resource(state, query, &language, &entities).await;
+    let answer = state
+        .dbpedia
+        .get_summary(&dbpedia_resource, &language)
         .await
         .unwrap_or(String::from(""));
+    let panel = get_knowledge_panel(&state.dbpedia, &dbpedia_resource, &language).await;
 
     Ok(Json(Answer {
         urls,
-        small_summary: (&answer).into(),
+        small_summary: match &panel {
+            Some(panel) => panel.summary.clone(),
+            None => truncate_at_sentence(&answer, PANEL_SUMMARY_LENGTH),
+        },
         answer,
-        corrected: query.into(),
+        corrected: state.speller.correct(query),
+        panel,
     }))
 }
 
-#[get("/?<query>&<page>&<language_option>")]
+#[get("/?<query>&<page>&<page_size>&<language_option>&<federated>")]
 async fn _results(
     state: &State<Config>,
     query: &str,
     page: usize,
+    page_size: Option<usize>,
     language_option: Option<&str>,
+    federated: Option<bool>,
 ) -> Result<Json<Results>, Error> {
-    let page_size = 5;
+    // Peers merging their rankings ask for every hit up to the page they serve.
+    let page_size = page_size.unwrap_or(PAGE_SIZE);
+    if page > MAX_PAGE || page_size == 0 || page_size.saturating_mul(page) > MAX_PAGE * PAGE_SIZE {
+        return Err(Error::BadRequest);
+    }
 
-    match get_url_list(
-        &state.http_client,
-        &state.embeddings,
-        &state.vec_index,
-        &state.db,
+    let (urls, _) = get_federated_url_list(
+        state,
         query,
         page,
         page_size,
         language_option,
+        federated.unwrap_or(false),
     )
-    .await
-    {
-        Ok(urls) => Ok(Json(Results { urls })),
-        Err(_) => Err(Error::InternalServerError),
-    }
+    .await?;
+
+    Ok(Json(Results { urls }))
 }
 
-#[get("/?<query>")]
-async fn _summary(state: &State<Config>, query: &str) -> Result<Json<Summary>, Error> {
-    let dbpedia_resource = dbpedia::get_resource(&state.http_client, query)
-        .await
-        .unwrap_or(String::from(""));
-    let small_summary = dbpedia::get_summary(&state.http_client, &dbpedia_resource)
+#[get("/?<query>&<language_option>")]
+async fn _summary(
+    state: &State<Config>,
+    query: &str,
+    language_option: Option<&str>,
+) -> Result<Json<Summary>, Error> {
+    let language = get_query_language(state, query, language_option);
+    let entities = link_entities(
+        &state.dbpedia,
+        state.embeddings.as_ref(),
+        &state.weighting,
+        query,
+        &language,
+    )
+    .await;
+    let dbpedia_resource = get_answer_resource(state, query, &language, &entities).await;
+    let small_summary = state
+        .dbpedia
+        .get_summary(&dbpedia_resource, &language)
         .await
         .unwrap_or(String::from(""));
 
     Ok(Json(Summary {
         small_summary,
-        corrected: query.into(),
+        corrected: state.speller.correct(query),
     }))
 }
 
+#[get("/<id>")]
+fn _get_doc(state: &State<Config>, id: &str) -> Result<Json<CrawledEntry>, Error> {
+    let id = parse_entry_id(id).ok_or(Error::BadRequest)?;
+    match get_entry(&state.db, id) {
+        Ok(Some(entry)) => Ok(Json(entry)),
+        Ok(None) => Err(Error::NotFound),
+        Err(_) => Err(Error::InternalServerError),
+    }
+}
+
+#[get("/<id>?<page>&<language_option>")]
+fn _similar(
+    state: &State<Config>,
+    id: &str,
+    page: Option<usize>,
+    language_option: Option<&str>,
+) -> Result<Json<Results>, Error> {
+    let page_size = PAGE_SIZE;
+    let id = parse_entry_id(id).ok_or(Error::BadRequest)?;
+    if page.unwrap_or(1) > MAX_PAGE {
+        return Err(Error::BadRequest);
+    }
+    match get_similar_url_list(
+        &state.vec_index.current(),
+        &state.db,
+        state.embeddings.dimension(),
+        id,
+        page.unwrap_or(1),
+        page_size,
+        language_option,
+    ) {
+        Ok(Some(urls)) => Ok(Json(Results { urls })),
+        Ok(None) => Err(Error::NotFound),
+        Err(_) => Err(Error::InternalServerError),
+    }
+}
+
+#[get("/?<prefix>&<limit>")]
+fn _suggest(state: &State<Config>, prefix: &str, limit: Option<usize>) -> Json<Suggestions> {
+    let limit = limit.unwrap_or(MAX_SUGGESTIONS).min(MAX_SUGGESTIONS);
+    Json(Suggestions {
+        suggestions: state.suggester.suggest(prefix, limit),
+    })
+}
+
 #[get("/")]
 fn _get_peers(state: &State<Config>) -> Result<Json<Peers>, Status> {
     let peers: Vec<Peer> = state
@@ -227,25 +518,86 @@ async fn _update_peer(state: &State<Config>, peer: Json<Peer>) -> Result<Json<Pe
     return _add_peer(state, peer).await;
 }
 
+#[post("/", format = "json", data = "<entry>")]
+async fn _ingest(
+    state: &State<Config>,
+    _authorized: IngestAuthorized,
+    entry: Json<CrawledEntry>,
+) -> Result<Json<CrawledEntry>, Error> {
+    let mut entry = entry.0;
+    if let Ok(anchors) = get_anchor_texts(&state.db, get_entry_id(&entry.url)) {
+        entry.anchors = anchors;
+    }
+
+    // Entries are embedded again so they match the model and weighting this server queries with.
+    match get_entry_embedding(
+        &state.dbpedia,
+        state.embeddings.as_ref(),
+        &state.weighting,
+        &entry,
+    )
+    .await
+    {
+        Some(vec) => entry.vec = vec.to_vec(),
+        None if entry.vec.len() == state.embeddings.dimension() => (),
+        None => return Err(Error::NotAcceptable),
+    }
+
+    entry.passages = get_passage_embeddings(state.embeddings.as_ref(), &state.weighting, &entry);
+
+    match store_entry(&state.db, &entry) {
+        Ok(_) => {
+            if let Err(e) =
+                state
+                    .suggester
+                    .add_title(&state.db, get_entry_id(&entry.url), &entry.title)
+            {
+                println!("Error: {:?}. Error adding title to suggestions.", e);
+            }
+            state.speller.add_text(&format!(
+                "{} {} {} {}",
+                entry.title, entry.header, entry.description, entry.body
+            ));
+            Ok(Json(entry))
+        }
+        Err(_) => Err(Error::InternalServerError),
+    }
+}
+
+#[delete("/?<url>")]
+async fn _remove(
+    state: &State<Config>,
+    _authorized: IngestAuthorized,
+    url: &str,
+) -> Result<Status, Error> {
+    match remove_entry(&state.db, get_entry_id(url)) {
+        Ok(Some(_)) => {
+            if let Err(e) = state.suggester.remove_title(get_entry_id(url)) {
+                println!("Error: {:?}. Error removing title from suggestions.", e);
+            }
+            Ok(Status::NoContent)
+        }
+        Ok(None) => Err(Error::NotFound),
+        Err(_) => Err(Error::InternalServerError),
+    }
+}
+
 #[launch]
 async fn rocket() -> _ {
-    let mut p = project_root::get_project_root().unwrap();
-    p.push("glove.6B/glove.6B.50d.txt");
-    let mut reader = BufReader::new(File::open(p).unwrap());
-
     let http_client = reqwest::Client::new();
-    let embeddings = Embeddings::read_text(&mut reader).unwrap();
+    let embeddings = load_embedder_from_env().unwrap();
     let db = sled::open("urlDatabase").expect("open");
     let peers = sled::open("peerDatabase").expect("open");
-    let mut vec_index = hora::index::hnsw_idx::HNSWIndex::<f32, u128>::new(
-        50,
-        &hora::index::hnsw_params::HNSWParams::<f32>::default(),
-    );
 
     let this_peer = Peer {
         address: var("PEAR_ADDRESS").unwrap(),
     };
 
+    let peer_timeout = Duration::from_millis(match var("PEAR_TIMEOUT_MS") {
+        Ok(number) => number.parse().unwrap_or(2000),
+        Err(_) => 2000,
+    });
+
     let mut peer_list: Vec<Peer> = match var("PEAR_SYNC_WITH") {
         Ok(address) => match http_client.get(format!("{}/_peers", address)).send().await {
             Ok(json_value) => match json_value.json::<Peers>().await {
@@ -296,30 +648,142 @@ async fn rocket() -> _ {
         }
     }
 
-    for url in db.iter() {
-        if let Ok(url) = url {
-            let url_key: u128 = String::from_utf8_lossy(&url.0).parse().unwrap();
-            match json::from_str::<CrawledEntry>(String::from_utf8_lossy(&url.1).as_ref()) {
-                Ok(url_value) => {
-                    vec_index.add(&url_value.vec, url_key).unwrap();
-                }
-                Err(e) => {
-                    println!("Error: {:?}. URL database deserialization error.", e);
-                }
+    if let Err(e) = ensure_lexical_index(&db) {
+        println!("Error: {:?}. Error building the lexical index.", e);
+    }
+    if let Err(e) = ensure_anchor_texts(&db) {
+        println!("Error: {:?}. Error moving the anchor texts.", e);
+    }
+    if let Err(e) = ensure_result_records(&db) {
+        println!("Error: {:?}. Error building the result records.", e);
+    }
+
+    let weighting = match load_weighting_from_env(&db) {
+        Ok(weighting) => weighting,
+        Err(e) => {
+            println!(
+                "Error: {:?}. Error loading term weights, using the plain mean.",
+                e
+            );
+            Weighting::mean()
+        }
+    };
+
+    let fusion = match var("FUSION").as_deref() {
+        Ok("weighted") => Fusion::Weighted {
+            vector_weight: match var("FUSION_VECTOR_WEIGHT") {
+                Ok(number) => number.parse().unwrap_or(0.5),
+                Err(_) => 0.5,
+            },
+            lexical_weight: match var("FUSION_LEXICAL_WEIGHT") {
+                Ok(number) => number.parse().unwrap_or(0.5),
+                Err(_) => 0.5,
+            },
+        },
+        _ => Fusion::ReciprocalRank {
+            k: match var("FUSION_RRF_K") {
+                Ok(number) => number.parse().unwrap_or(60.0),
+                Err(_) => 60.0,
+            },
+        },
+    };
+
+    let speller = Speller::new(embeddings.as_ref(), &db).unwrap();
+    let search_corrected = match var("SEARCH_CORRECTED") {
+        Ok(value) => value.parse().unwrap_or(true),
+        Err(_) => true,
+    };
+
+    let index_snapshot_path = String::from("urlDatabase.hnsw");
+    let vec_index = Arc::new(LiveIndex::new(load_or_build_index(
+        &db,
+        &index_snapshot_path,
+        embeddings.dimension(),
+    )));
+
+    let index_debounce = Duration::from_millis(match var("INDEX_DEBOUNCE_MS") {
+        Ok(number) => number.parse().unwrap_or(5000),
+        Err(_) => 5000,
+    });
+    let index_max_wait = Duration::from_millis(match var("INDEX_MAX_WAIT_MS") {
+        Ok(number) => number.parse().unwrap_or(60000),
+        Err(_) => 60000,
+    });
+    tokio::spawn(watch_index(
+        db.clone(),
+        vec_index.clone(),
+        index_snapshot_path,
+        embeddings.dimension(),
+        index_debounce,
+        index_max_wait,
+    ));
+
+    let dbpedia = load_dbpedia_from_env(http_client.clone(), Some(&db));
+    // Suggestions are kept apart from the entries, so recording queries leaves the index
+    // snapshot valid.
+    let suggest_db = sled::open("suggestDatabase").expect("open");
+    let suggester = Suggester::new(
+        &suggest_db,
+        Duration::from_secs(match var("QUERY_SUGGESTION_TTL") {
+            Ok(number) => number.parse().unwrap_or(90 * 24 * 60 * 60),
+            Err(_) => 90 * 24 * 60 * 60,
+        }),
+        match var("MAX_QUERY_SUGGESTIONS") {
+            Ok(number) => number.parse().unwrap_or(100000),
+            Err(_) => 100000,
+        },
+    )
+    .unwrap();
+    if let Err(e) = suggester.move_queries(&db) {
+        println!(
+            "Error: {:?}. Error moving queries to the suggestions database.",
+            e
+        );
+    }
+    match get_result_titles(&db) {
+        Ok(titles) => {
+            if let Err(e) = suggester.sync_titles(&db, titles) {
+                println!("Error: {:?}. Error adding titles to suggestions.", e);
             }
         }
+        Err(e) => println!("Error: {:?}. Error reading titles for suggestions.", e),
+    }
+    if let Err(e) = suggester.add_labels(dbpedia.get_labels()) {
+        println!("Error: {:?}. Error adding labels to suggestions.", e);
     }
 
-    vec_index
-        .build(hora::core::metrics::Metric::CosineSimilarity)
-        .unwrap();
+    let ingest_token = var("INGEST_TOKEN").ok().filter(|token| !token.is_empty());
+    let ingest_trust_loopback = match var("INGEST_TRUST_LOOPBACK") {
+        Ok(value) => value.parse().unwrap_or(false),
+        Err(_) => false,
+    };
+    if ingest_token.is_none() && !ingest_trust_loopback {
+        println!("INGEST_TOKEN is not set, so the ingest endpoints refuse every request.");
+    }
 
     let config = Config {
         vec_index,
         db,
         embeddings,
+        weighting,
         peers,
         http_client,
+        dbpedia,
+        this_peer,
+        peer_timeout,
+        ranking: Ranking {
+            fusion,
+            authority_weight: match var("AUTHORITY_WEIGHT") {
+                Ok(number) => number.parse().unwrap_or(0.2),
+                Err(_) => 0.2,
+            },
+        },
+        speller,
+        suggester,
+        search_corrected,
+        detector: LanguageDetectorBuilder::from_languages(&[English, Spanish]).build(),
+        ingest_token,
+        ingest_trust_loopback,
     };
 
     rocket::build()
@@ -327,6 +791,10 @@ async fn rocket() -> _ {
         .mount("/_answer", routes![_answer])
         .mount("/_results", routes![_results])
         .mount("/_summary", routes![_summary])
+        .mount("/_suggest", routes![_suggest])
+        .mount("/_doc", routes![_get_doc])
+        .mount("/_similar", routes![_similar])
         .mount("/_peers", routes![_get_peers])
         .mount("/_peer", routes![_get_peer, _add_peer, _update_peer])
+        .mount("/_ingest", routes![_ingest, _remove])
 }
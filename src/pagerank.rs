@@ -0,0 +1,108 @@
+use rocket::serde::json;
+use std::collections::HashMap;
+
+pub static LINKS_TREE: &str = "links";
+static PAGERANK_TREE: &str = "pagerank";
+
+/// Stores the outgoing links of the document `id` in the link graph.
+pub fn store_links(db: &sled::Db, id: u128, targets: &[u128]) -> sled::Result<()> {
+    let links = db.open_tree(LINKS_TREE)?;
+    let targets: Vec<String> = targets
+        .iter()
+        .filter(|target| **target != id)
+        .map(|target| target.to_string())
+        .collect();
+    links.insert(id.to_string(), json::to_string(&targets).unwrap().as_str())?;
+    Ok(())
+}
+
+pub fn remove_links(db: &sled::Db, id: u128) -> sled::Result<()> {
+    db.open_tree(LINKS_TREE)?.remove(id.to_string())?;
+    Ok(())
+}
+
+fn load_link_graph(db: &sled::Db) -> sled::Result<HashMap<u128, Vec<u128>>> {
+    let links = db.open_tree(LINKS_TREE)?;
+    let mut graph: HashMap<u128, Vec<u128>> = HashMap::new();
+    for link in links.iter() {
+        let (key, value) = link?;
+        let source: u128 = match String::from_utf8_lossy(&key).parse() {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let targets = json::from_str::<Vec<String>>(String::from_utf8_lossy(&value).as_ref())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|target| target.parse().ok())
+            .collect();
+        graph.insert(source, targets);
+    }
+    Ok(graph)
+}
+
+/// Runs PageRank over the link graph and stores every score divided by the highest one, so the
+/// scores lie in `[0, 1]`. Pages without outgoing links spread their rank over every page.
+pub fn compute_pagerank(db: &sled::Db, damping: f32, iterations: usize) -> sled::Result<usize> {
+    let graph = load_link_graph(db)?;
+
+    let mut nodes: Vec<u128> = graph.keys().cloned().collect();
+    nodes.extend(graph.values().flatten().cloned());
+    nodes.sort();
+    nodes.dedup();
+    if nodes.is_empty() {
+        return Ok(0);
+    }
+
+    let positions: HashMap<u128, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(position, id)| (*id, position))
+        .collect();
+    let node_count = nodes.len() as f32;
+
+    let mut ranks = vec![1.0 / node_count; nodes.len()];
+    for _ in 0..iterations {
+        let mut next = vec![(1.0 - damping) / node_count; nodes.len()];
+        let mut dangling = 0.0;
+
+        for (position, id) in nodes.iter().enumerate() {
+            match graph.get(id) {
+                Some(targets) if !targets.is_empty() => {
+                    let share = damping * ranks[position] / targets.len() as f32;
+                    for target in targets {
+                        next[positions[target]] += share;
+                    }
+                }
+                _ => dangling += damping * ranks[position],
+            }
+        }
+
+        for rank in next.iter_mut() {
+            *rank += dangling / node_count;
+        }
+        ranks = next;
+    }
+
+    let highest = ranks.iter().cloned().fold(f32::MIN, f32::max);
+    let pagerank = db.open_tree(PAGERANK_TREE)?;
+    pagerank.clear()?;
+    for (id, rank) in nodes.iter().zip(ranks) {
+        pagerank.insert(id.to_string(), (rank / highest).to_be_bytes().to_vec())?;
+    }
+
+    Ok(nodes.len())
+}
+
+/// Stored PageRank of the document `id`, zero if it has not been computed.
+pub fn get_pagerank(db: &sled::Db, id: u128) -> f32 {
+    let rank = db
+        .open_tree(PAGERANK_TREE)
+        .and_then(|pagerank| pagerank.get(id.to_string()));
+
+    match rank {
+        Ok(Some(value)) => <[u8; 4]>::try_from(value.as_ref())
+            .map(f32::from_be_bytes)
+            .unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
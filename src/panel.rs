@@ -0,0 +1,241 @@
+use crate::dbpedia::Dbpedia;
+use rocket::serde::{Deserialize, Serialize};
+
+static RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+static RDFS_LABEL: &str = "http://www.w3.org/2000/01/rdf-schema#label";
+static DBO: &str = "http://dbpedia.org/ontology/";
+static DBO_ABSTRACT: &str = "http://dbpedia.org/ontology/abstract";
+static DBO_THUMBNAIL: &str = "http://dbpedia.org/ontology/thumbnail";
+static FOAF_PRIMARY_TOPIC_OF: &str = "http://xmlns.com/foaf/0.1/isPrimaryTopicOf";
+
+/// Characters the abstract of a panel is cut down to, at a sentence boundary.
+pub static PANEL_SUMMARY_LENGTH: usize = 300;
+/// Values shown for a property that has several.
+static MAX_PROPERTY_VALUES: usize = 3;
+
+/// Properties shown for each DBpedia ontology class, as their name and ontology property. The
+/// first class an entity belongs to is used, so specific classes come before general ones.
+static PANEL_TYPES: [(&str, &[(&str, &str)]); 9] = [
+    (
+        "Person",
+        &[
+            ("Born", "birthDate"),
+            ("Birth place", "birthPlace"),
+            ("Died", "deathDate"),
+            ("Occupation", "occupation"),
+        ],
+    ),
+    (
+        "Country",
+        &[
+            ("Capital", "capital"),
+            ("Population", "populationTotal"),
+            ("Currency", "currency"),
+        ],
+    ),
+    (
+        "PopulatedPlace",
+        &[
+            ("Country", "country"),
+            ("Population", "populationTotal"),
+            ("Area", "areaTotal"),
+        ],
+    ),
+    (
+        "Company",
+        &[
+            ("Founded", "foundingDate"),
+            ("Headquarters", "headquarter"),
+            ("Industry", "industry"),
+            ("Employees", "numberOfEmployees"),
+        ],
+    ),
+    (
+        "Organisation",
+        &[
+            ("Founded", "formationDate"),
+            ("Headquarters", "headquarter"),
+        ],
+    ),
+    (
+        "Film",
+        &[
+            ("Director", "director"),
+            ("Released", "releaseDate"),
+            ("Running time", "runtime"),
+        ],
+    ),
+    (
+        "Book",
+        &[("Author", "author"), ("Published", "releaseDate")],
+    ),
+    ("Species", &[("Kingdom", "kingdom"), ("Family", "family")]),
+    ("Place", &[("Country", "country"), ("Location", "location")]),
+];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Property {
+    pub name: String,
+    pub value: String,
+}
+
+/// Info box about the entity a query is about.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KnowledgePanel {
+    pub resource: String,
+    pub label: String,
+    /// DBpedia ontology class of the entity, such as `Person`.
+    pub entity_type: Option<String>,
+    /// Abstract of the entity, cut at a sentence boundary.
+    pub summary: String,
+    pub thumbnail: Option<String>,
+    pub wikipedia: Option<String>,
+    pub properties: Vec<Property>,
+}
+
+/// Every predicate a panel may be built from.
+pub fn get_panel_predicates() -> Vec<String> {
+    let mut predicates: Vec<String> = vec![
+        RDF_TYPE.to_string(),
+        RDFS_LABEL.to_string(),
+        DBO_ABSTRACT.to_string(),
+        DBO_THUMBNAIL.to_string(),
+        FOAF_PRIMARY_TOPIC_OF.to_string(),
+    ];
+    for (_, properties) in PANEL_TYPES.iter() {
+        for (_, property) in properties.iter() {
+            predicates.push(format!("{}{}", DBO, property));
+        }
+    }
+    predicates.sort();
+    predicates.dedup();
+    predicates
+}
+
+/// Readable form of a fact value, the page name for DBpedia resources.
+fn get_display_value(value: &str) -> String {
+    match value.rsplit_once("/resource/") {
+        Some((_, name)) => name.replace("_", " "),
+        None => value.to_string(),
+    }
+}
+
+/// Longest run of whole sentences of `text` that fits in `length` characters, or its first
+/// words if the first sentence is already longer.
+pub fn truncate_at_sentence(text: &str, length: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+
+    let mut end = None;
+    let mut last_word_end = 0;
+    let mut characters = text.char_indices().peekable();
+    let mut count = 0;
+    while let Some((position, character)) = characters.next() {
+        count += 1;
+        if count > length {
+            break;
+        }
+
+        let next = characters.peek().map(|(_, next)| *next);
+        if next.map_or(true, char::is_whitespace) {
+            last_word_end = position + character.len_utf8();
+            if ['.', '!', '?'].contains(&character) {
+                end = Some(last_word_end);
+            }
+        }
+    }
+
+    match end {
+        Some(end) => text[..end].to_string(),
+        None if last_word_end == 0 => format!("{}…", text.chars().take(length).collect::<String>()),
+        None => format!("{}…", &text[..last_word_end]),
+    }
+}
+
+/// Builds the knowledge panel of `resource` in `language`, `None` if the knowledge base knows
+/// nothing of it.
+pub async fn get_knowledge_panel(
+    dbpedia: &Dbpedia,
+    resource: &str,
+    language: &str,
+) -> Option<KnowledgePanel> {
+    let facts = match dbpedia.get_facts(resource, language).await {
+        Ok(facts) if !facts.is_empty() => facts,
+        Ok(_) => return None,
+        Err(e) => {
+            println!("Error: {:?}. Error fetching facts of {}.", e, resource);
+            return None;
+        }
+    };
+    let get_values = |predicate: &str| -> Vec<&str> {
+        facts
+            .iter()
+            .filter(|(fact_predicate, _)| fact_predicate == predicate)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    };
+
+    let types = get_values(RDF_TYPE);
+    let panel_type = PANEL_TYPES.iter().find(|(class, _)| {
+        types
+            .iter()
+            .any(|value| *value == format!("{}{}", DBO, class))
+    });
+
+    let entity_type = match panel_type {
+        Some((class, _)) => Some(class.to_string()),
+        None => types
+            .iter()
+            .find_map(|value| value.strip_prefix(DBO))
+            .map(String::from),
+    };
+
+    let properties = match panel_type {
+        Some((_, properties)) => properties
+            .iter()
+            .filter_map(|(name, property)| {
+                let values = get_values(&format!("{}{}", DBO, property));
+                if values.is_empty() {
+                    return None;
+                }
+                Some(Property {
+                    name: name.to_string(),
+                    value: values
+                        .iter()
+                        .take(MAX_PROPERTY_VALUES)
+                        .map(|value| get_display_value(value))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                })
+            })
+            .collect(),
+        None => vec![],
+    };
+
+    let summary = match get_values(DBO_ABSTRACT).first() {
+        Some(summary) => summary.to_string(),
+        None => dbpedia
+            .get_summary(resource, language)
+            .await
+            .unwrap_or_default(),
+    };
+
+    Some(KnowledgePanel {
+        resource: resource.to_string(),
+        label: match get_values(RDFS_LABEL).first() {
+            Some(label) => label.to_string(),
+            None => get_display_value(resource),
+        },
+        entity_type,
+        summary: truncate_at_sentence(&summary, PANEL_SUMMARY_LENGTH),
+        thumbnail: get_values(DBO_THUMBNAIL)
+            .first()
+            .map(|value| value.to_string()),
+        wikipedia: get_values(FOAF_PRIMARY_TOPIC_OF)
+            .first()
+            .map(|value| value.to_string()),
+        properties,
+    })
+}
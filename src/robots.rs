@@ -0,0 +1,200 @@
+use reqwest::Url;
+use std::time::Duration;
+
+/// Longest robots.txt read, in bytes. Rules past it are ignored.
+static MAX_ROBOTS_LENGTH: usize = 500 * 1024;
+
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+/// Rules of a robots.txt that apply to one user agent.
+pub struct Robots {
+    rules: Vec<Rule>,
+    /// Time to wait between requests to the host, if it asks for one.
+    pub crawl_delay: Option<Duration>,
+    /// Sitemaps the host lists, for every user agent.
+    pub sitemaps: Vec<String>,
+}
+
+/// Name robots.txt groups address `user_agent` by, such as `tree` for `tree/0.1 (+url)`.
+pub fn get_product_token(user_agent: &str) -> String {
+    user_agent
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .split('/')
+        .next()
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// Whether `path` matches a robots.txt `pattern`, where `*` matches any run of characters and
+/// a trailing `$` anchors the pattern at the end of the path.
+fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut position = 0;
+    for (index, part) in parts.iter().enumerate() {
+        if index == 0 {
+            if !path.starts_with(part) {
+                return false;
+            }
+            position = part.len();
+        } else if anchored && index == parts.len() - 1 {
+            return path.len() >= position + part.len() && path.ends_with(part);
+        } else {
+            match path[position..].find(part) {
+                Some(found) => position += found + part.len(),
+                None => return false,
+            }
+        }
+    }
+
+    !anchored || position == path.len()
+}
+
+impl Robots {
+    pub fn allow_all() -> Self {
+        Self {
+            rules: vec![],
+            crawl_delay: None,
+            sitemaps: vec![],
+        }
+    }
+
+    pub fn disallow_all() -> Self {
+        Self {
+            rules: vec![Rule {
+                allow: false,
+                pattern: String::from("/"),
+            }],
+            crawl_delay: None,
+            sitemaps: vec![],
+        }
+    }
+
+    /// Parses `text` keeping the groups that address the product token of `user_agent`, matched
+    /// case-insensitively, or the `*` group if none does.
+    pub fn parse(text: &str, user_agent: &str) -> Self {
+        let token = get_product_token(user_agent);
+        let text = match text.len() > MAX_ROBOTS_LENGTH {
+            true => {
+                let end = (0..=MAX_ROBOTS_LENGTH)
+                    .rev()
+                    .find(|end| text.is_char_boundary(*end))
+                    .unwrap_or(0);
+                &text[..end]
+            }
+            false => text,
+        };
+
+        let mut groups: Vec<Group> = Vec::new();
+        let mut sitemaps = Vec::new();
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    // Consecutive user agent lines share the rules that follow them.
+                    if !in_agents {
+                        groups.push(Group {
+                            agents: vec![],
+                            rules: vec![],
+                            crawl_delay: None,
+                        });
+                    }
+                    in_agents = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_lowercase());
+                    }
+                    continue;
+                }
+                "allow" | "disallow" if !value.is_empty() => {
+                    if let Some(group) = groups.last_mut() {
+                        group.rules.push(Rule {
+                            allow: key == "allow",
+                            pattern: value.to_string(),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    if let (Some(group), Ok(seconds)) = (groups.last_mut(), value.parse::<f64>()) {
+                        if seconds.is_finite() && seconds >= 0.0 {
+                            group.crawl_delay = Some(Duration::from_secs_f64(seconds));
+                        }
+                    }
+                }
+                "sitemap" => sitemaps.push(value.to_string()),
+                _ => (),
+            }
+            in_agents = false;
+        }
+
+        // Groups naming the product token exactly go before the `*` group.
+        let get_specificity = |group: &Group| -> Option<usize> {
+            group
+                .agents
+                .iter()
+                .filter_map(|agent| match agent.as_str() {
+                    "*" => Some(0),
+                    agent if !token.is_empty() && agent == token => Some(1),
+                    _ => None,
+                })
+                .max()
+        };
+        let best = groups.iter().filter_map(&get_specificity).max();
+
+        let mut robots = Self::allow_all();
+        robots.sitemaps = sitemaps;
+        for group in groups {
+            if best.is_some() && get_specificity(&group) == best {
+                robots.rules.extend(group.rules);
+                robots.crawl_delay = robots.crawl_delay.or(group.crawl_delay);
+            }
+        }
+        robots
+    }
+
+    /// Whether `url` may be fetched. The longest matching rule decides, allowing on ties.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        let mut best: Option<&Rule> = None;
+        for rule in self.rules.iter() {
+            if !matches_pattern(&rule.pattern, &path) {
+                continue;
+            }
+            best = match best {
+                Some(best)
+                    if best.pattern.len() > rule.pattern.len()
+                        || (best.pattern.len() == rule.pattern.len() && best.allow) =>
+                {
+                    Some(best)
+                }
+                _ => Some(rule),
+            };
+        }
+
+        best.map_or(true, |rule| rule.allow)
+    }
+}
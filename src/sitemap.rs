@@ -0,0 +1,190 @@
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+/// Longest sitemap read, in bytes, both as served and once uncompressed, as the sitemap
+/// protocol allows.
+pub static MAX_SITEMAP_LENGTH: u64 = 50 * 1024 * 1024;
+
+/// Bytes gzip files start with.
+static GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Url a sitemap lists, with the optional hints the sitemap gives about it.
+pub struct SitemapEntry {
+    pub url: String,
+    /// Last modification, in seconds since the epoch.
+    pub lastmod: Option<u64>,
+    /// Importance of the url relative to the others of its site, from 0 to 1.
+    pub priority: Option<f32>,
+}
+
+pub enum Sitemap {
+    /// Sitemap index, listing other sitemaps.
+    Index(Vec<SitemapEntry>),
+    /// Sitemap listing pages.
+    Urls(Vec<SitemapEntry>),
+}
+
+/// Text of a sitemap served as `bytes`, uncompressing gzipped sitemaps such as `sitemap.xml.gz`.
+pub fn read_sitemap(bytes: &[u8]) -> std::io::Result<String> {
+    let mut text = Vec::new();
+    if bytes.starts_with(&GZIP_MAGIC) {
+        GzDecoder::new(bytes)
+            .take(MAX_SITEMAP_LENGTH)
+            .read_to_end(&mut text)?;
+    } else {
+        Read::take(bytes, MAX_SITEMAP_LENGTH).read_to_end(&mut text)?;
+    }
+    Ok(String::from_utf8_lossy(&text).to_string())
+}
+
+/// Unescapes the entities XML requires in sitemap urls.
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Text of an element, which may be wrapped in a CDATA section.
+fn get_text(content: &str) -> String {
+    let content = content.trim();
+    match content
+        .strip_prefix("<![CDATA[")
+        .and_then(|content| content.strip_suffix("]]>"))
+    {
+        Some(content) => content.trim().to_string(),
+        None => unescape_xml(content),
+    }
+}
+
+/// Contents of the `name` elements of `xml`, in order.
+fn get_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        // `<url` also starts `<urlset`.
+        match rest.chars().next() {
+            Some(next) if next == '>' || next.is_whitespace() => (),
+            _ => continue,
+        }
+        let content_start = match rest.find('>') {
+            Some(end) => end + 1,
+            None => break,
+        };
+        rest = &rest[content_start..];
+        match rest.find(&close) {
+            Some(end) => {
+                elements.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    elements
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn get_days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Seconds since the epoch of a W3C datetime, the format of `lastmod`, such as `2022-06-01`
+/// or `2022-06-01T10:30:00+02:00`.
+pub fn parse_lastmod(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let mut parts = date.split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next().map_or(Some(1), |month| month.parse().ok())?;
+    let day: i64 = parts.next().map_or(Some(1), |day| day.parse().ok())?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds = get_days_since_epoch(year, month, day) * 24 * 60 * 60;
+
+    if let Some(time) = time {
+        let (clock, offset) = match time.find(|c| c == 'Z' || c == '+' || c == '-') {
+            Some(start) => (&time[..start], &time[start..]),
+            None => (time, ""),
+        };
+        let mut clock_parts = clock.split(':');
+        let hours: i64 = clock_parts.next()?.parse().ok()?;
+        let minutes: i64 = clock_parts.next().unwrap_or("0").parse().ok()?;
+        let clock_seconds: f64 = clock_parts.next().unwrap_or("0").parse().ok()?;
+        seconds += hours * 60 * 60 + minutes * 60 + clock_seconds as i64;
+
+        if let Some(sign @ ('+' | '-')) = offset.chars().next() {
+            let (offset_hours, offset_minutes) = offset[1..].split_once(':')?;
+            let offset = offset_hours.parse::<i64>().ok()? * 60 * 60
+                + offset_minutes.parse::<i64>().ok()? * 60;
+            seconds += if sign == '+' { -offset } else { offset };
+        }
+    }
+
+    u64::try_from(seconds).ok()
+}
+
+fn parse_entry(element: &str) -> Option<SitemapEntry> {
+    let url = get_text(get_elements(element, "loc").first()?);
+    if url.is_empty() {
+        return None;
+    }
+    Some(SitemapEntry {
+        url,
+        lastmod: get_elements(element, "lastmod")
+            .first()
+            .and_then(|lastmod| parse_lastmod(&get_text(lastmod))),
+        priority: get_elements(element, "priority")
+            .first()
+            .and_then(|priority| get_text(priority).parse::<f32>().ok())
+            .filter(|priority| priority.is_finite())
+            .map(|priority| priority.clamp(0.0, 1.0)),
+    })
+}
+
+/// Parses an XML sitemap or sitemap index, or a text sitemap listing one url per line.
+pub fn parse_sitemap(text: &str) -> Sitemap {
+    let text = text.trim_start_matches('\u{feff}');
+    if !text.trim_start().starts_with('<') {
+        return Sitemap::Urls(
+            text.lines()
+                .map(|line| line.trim())
+                .filter(|line| line.starts_with("http://") || line.starts_with("https://"))
+                .map(|line| SitemapEntry {
+                    url: line.to_string(),
+                    lastmod: None,
+                    priority: None,
+                })
+                .collect(),
+        );
+    }
+
+    if text.contains("<sitemapindex") {
+        Sitemap::Index(
+            get_elements(text, "sitemap")
+                .into_iter()
+                .filter_map(parse_entry)
+                .collect(),
+        )
+    } else {
+        Sitemap::Urls(
+            get_elements(text, "url")
+                .into_iter()
+                .filter_map(parse_entry)
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,339 @@
+use rocket::serde::de::DeserializeOwned;
+use rocket::serde::Deserialize;
+
+/// Term of a triple pattern. Iris and literals are escaped when the query is built, so they can
+/// hold user input.
+pub enum Term {
+    Variable(String),
+    Iri(String),
+    Prefixed(&'static str, &'static str),
+    Literal(String, Option<String>),
+}
+
+impl Term {
+    pub fn variable(name: &str) -> Self {
+        Term::Variable(name.to_string())
+    }
+
+    pub fn iri(iri: &str) -> Self {
+        Term::Iri(iri.to_string())
+    }
+
+    pub fn literal(value: &str, language: Option<&str>) -> Self {
+        Term::Literal(value.to_string(), language.map(String::from))
+    }
+
+    fn to_sparql(&self) -> String {
+        match self {
+            Term::Variable(name) => format!("?{}", escape_variable(name)),
+            Term::Iri(iri) => format!("<{}>", escape_iri(iri)),
+            Term::Prefixed(prefix, local) => format!("{}:{}", prefix, local),
+            Term::Literal(value, language) => {
+                match language.as_deref().map(escape_language).as_deref() {
+                    Some("") | None => format!("\"{}\"", escape_literal(value)),
+                    Some(language) => format!("\"{}\"@{}", escape_literal(value), language),
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the characters a variable name may hold.
+fn escape_variable(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric() || *c == '_')
+        .collect()
+}
+
+/// Percent encodes the characters an `IRIREF` may not hold.
+pub fn escape_iri(iri: &str) -> String {
+    let mut escaped = String::new();
+    for c in iri.chars() {
+        match c {
+            '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' | '\u{0}'..='\u{20}' => {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    escaped.push_str(&format!("%{:02X}", byte));
+                }
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes a string for use between double quotes.
+pub fn escape_literal(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\'' => escaped.push_str("\\'"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Keeps the characters a language tag may hold.
+fn escape_language(language: &str) -> String {
+    language
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect()
+}
+
+pub struct Triple(pub Term, pub Term, pub Term);
+
+impl Triple {
+    fn to_sparql(&self) -> String {
+        format!(
+            "{} {} {} .",
+            self.0.to_sparql(),
+            self.1.to_sparql(),
+            self.2.to_sparql()
+        )
+    }
+}
+
+enum Pattern {
+    Triple(Triple),
+    Union(Vec<Vec<Triple>>),
+    RankedUnion(String, Vec<Vec<Triple>>),
+    Language(String, String),
+    LiteralLanguage(String, String),
+    NotExists(Triple),
+    Values(String, Vec<Term>),
+}
+
+impl Pattern {
+    fn to_sparql(&self) -> String {
+        match self {
+            Pattern::Triple(triple) => triple.to_sparql(),
+            Pattern::Union(groups) => groups
+                .iter()
+                .map(|group| {
+                    let triples: Vec<String> = group.iter().map(Triple::to_sparql).collect();
+                    format!("{{ {} }}", triples.join(" "))
+                })
+                .collect::<Vec<String>>()
+                .join(" UNION "),
+            Pattern::RankedUnion(variable, groups) => groups
+                .iter()
+                .enumerate()
+                .map(|(rank, group)| {
+                    let triples: Vec<String> = group.iter().map(Triple::to_sparql).collect();
+                    format!(
+                        "{{ {} BIND ({} AS ?{}) }}",
+                        triples.join(" "),
+                        rank,
+                        escape_variable(variable)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(" UNION "),
+            Pattern::Language(variable, language) => format!(
+                "FILTER (langMatches(lang(?{}), {}))",
+                escape_variable(variable),
+                Term::literal(language, None).to_sparql()
+            ),
+            Pattern::LiteralLanguage(variable, language) => format!(
+                "FILTER (!isLiteral(?{0}) || lang(?{0}) = \"\" || langMatches(lang(?{0}), {1}))",
+                escape_variable(variable),
+                Term::literal(language, None).to_sparql()
+            ),
+            Pattern::NotExists(triple) => format!("FILTER NOT EXISTS {{ {} }}", triple.to_sparql()),
+            Pattern::Values(variable, terms) => format!(
+                "VALUES ?{} {{ {} }}",
+                escape_variable(variable),
+                terms
+                    .iter()
+                    .map(Term::to_sparql)
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+        }
+    }
+}
+
+/// SPARQL `SELECT` query.
+pub struct Select {
+    prefixes: Vec<(&'static str, &'static str)>,
+    distinct: bool,
+    variables: Vec<String>,
+    patterns: Vec<Pattern>,
+    group_by: Option<String>,
+    order_by_min: Option<String>,
+    limit: Option<usize>,
+}
+
+impl Select {
+    pub fn new(variables: &[&str]) -> Self {
+        Self {
+            prefixes: vec![],
+            distinct: false,
+            variables: variables
+                .iter()
+                .map(|variable| variable.to_string())
+                .collect(),
+            patterns: vec![],
+            group_by: None,
+            order_by_min: None,
+            limit: None,
+        }
+    }
+
+    pub fn prefix(mut self, prefix: &'static str, iri: &'static str) -> Self {
+        self.prefixes.push((prefix, iri));
+        self
+    }
+
+    pub fn triple(mut self, subject: Term, predicate: Term, object: Term) -> Self {
+        self.patterns
+            .push(Pattern::Triple(Triple(subject, predicate, object)));
+        self
+    }
+
+    pub fn union(mut self, groups: Vec<Vec<Triple>>) -> Self {
+        self.patterns.push(Pattern::Union(groups));
+        self
+    }
+
+    /// Union of `groups` binding `variable` to the position of the group each solution comes
+    /// from, so solutions can be ordered by the group they matched.
+    pub fn ranked_union(mut self, variable: &str, groups: Vec<Vec<Triple>>) -> Self {
+        self.patterns
+            .push(Pattern::RankedUnion(variable.to_string(), groups));
+        self
+    }
+
+    /// Keeps the solutions whose `variable` is a literal in `language`.
+    pub fn language(mut self, variable: &str, language: &str) -> Self {
+        self.patterns.push(Pattern::Language(
+            variable.to_string(),
+            language.to_string(),
+        ));
+        self
+    }
+
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+
+    /// Keeps the solutions matching no `triple`.
+    pub fn not_exists(mut self, triple: Triple) -> Self {
+        self.patterns.push(Pattern::NotExists(triple));
+        self
+    }
+
+    /// Keeps the solutions whose `variable` is an iri, an untagged literal or a literal in
+    /// `language`.
+    pub fn literal_language(mut self, variable: &str, language: &str) -> Self {
+        self.patterns.push(Pattern::LiteralLanguage(
+            variable.to_string(),
+            language.to_string(),
+        ));
+        self
+    }
+
+    /// Binds `variable` to each of `terms` in turn.
+    pub fn values(mut self, variable: &str, terms: Vec<Term>) -> Self {
+        self.patterns
+            .push(Pattern::Values(variable.to_string(), terms));
+        self
+    }
+
+    /// Returns one solution per value of `variable`.
+    pub fn group_by(mut self, variable: &str) -> Self {
+        self.group_by = Some(variable.to_string());
+        self
+    }
+
+    /// Orders the groups by the lowest value of `variable` among their solutions.
+    pub fn order_by_min(mut self, variable: &str) -> Self {
+        self.order_by_min = Some(variable.to_string());
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let mut query = String::new();
+        for (prefix, iri) in &self.prefixes {
+            query.push_str(&format!("PREFIX {}: <{}> ", prefix, escape_iri(iri)));
+        }
+
+        let variables: Vec<String> = self
+            .variables
+            .iter()
+            .map(|variable| format!("?{}", escape_variable(variable)))
+            .collect();
+        let patterns: Vec<String> = self.patterns.iter().map(Pattern::to_sparql).collect();
+        query.push_str(&format!(
+            "SELECT {}{} WHERE {{ {} }}",
+            if self.distinct { "DISTINCT " } else { "" },
+            variables.join(" "),
+            patterns.join(" ")
+        ));
+
+        if let Some(variable) = &self.group_by {
+            query.push_str(&format!(" GROUP BY ?{}", escape_variable(variable)));
+        }
+        if let Some(variable) = &self.order_by_min {
+            query.push_str(&format!(" ORDER BY MIN(?{})", escape_variable(variable)));
+        }
+        if let Some(limit) = self.limit {
+            query.push_str(&format!(" LIMIT {}", limit));
+        }
+        query
+    }
+}
+
+/// Value bound to a variable in a solution.
+#[derive(Deserialize, Debug)]
+pub struct Binding {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+    #[serde(rename = "xml:lang")]
+    pub language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Solutions<B> {
+    bindings: Vec<B>,
+}
+
+#[derive(Deserialize)]
+struct Results<B> {
+    results: Solutions<B>,
+}
+
+/// Runs `query` against `endpoint` and deserializes each solution into `B`, a struct with a
+/// `Binding` field per variable. Variables that are optional in the query must be `Option`s.
+pub async fn select<B: DeserializeOwned>(
+    client: &reqwest::Client,
+    endpoint: &str,
+    query: &Select,
+) -> Result<Vec<B>, reqwest::Error> {
+    let params = vec![("query", query.build()), ("output", "json".to_string())];
+    let results = client
+        .get(endpoint)
+        .query(&params)
+        .header("Accept", "application/sparql-results+json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Results<B>>()
+        .await?;
+
+    Ok(results.results.bindings)
+}
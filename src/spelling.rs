@@ -0,0 +1,229 @@
+use crate::lexical::{get_all_term_statistics, tokenize};
+use crate::Embedder;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Word of a BK-tree, with its children keyed by their edit distance to it.
+struct Node {
+    word: String,
+    children: HashMap<usize, usize>,
+}
+
+/// Known words and their frequencies, along with a BK-tree over the words so the ones close to
+/// a misspelling are found without comparing it to every word.
+struct Vocabulary {
+    frequencies: HashMap<String, f32>,
+    /// Terms counted in the crawled pages, which the frequencies of page terms are relative to.
+    term_count: f32,
+    nodes: Vec<Node>,
+}
+
+impl Vocabulary {
+    fn add_word(&mut self, word: String, frequency: f32) {
+        if let Some(known) = self.frequencies.get_mut(&word) {
+            *known += frequency;
+            return;
+        }
+        self.frequencies.insert(word.clone(), frequency);
+
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                word,
+                children: HashMap::new(),
+            });
+            return;
+        }
+
+        let mut position = 0;
+        loop {
+            let distance = get_edit_distance(&word, &self.nodes[position].word, usize::MAX)
+                .unwrap_or(usize::MAX);
+            match self.nodes[position].children.get(&distance) {
+                Some(child) => position = *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes[position].children.insert(distance, child);
+                    self.nodes.push(Node {
+                        word,
+                        children: HashMap::new(),
+                    });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Known words within `max_distance` edits of `word`, with their distance.
+    fn get_close_words(&self, word: &str, max_distance: usize) -> Vec<(usize, &String)> {
+        let mut close = vec![];
+        let mut positions = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(position) = positions.pop() {
+            let node = &self.nodes[position];
+            // By the triangle inequality, close words can only be under the children as far
+            // from this word as `word` is, give or take `max_distance`. Past the farthest child
+            // plus `max_distance` the exact distance does not matter, so it is not computed.
+            let farthest = node.children.keys().max().cloned().unwrap_or(0);
+            let distance = match get_edit_distance(word, &node.word, farthest + max_distance) {
+                Some(distance) => distance,
+                None => continue,
+            };
+            if distance <= max_distance {
+                close.push((distance, &node.word));
+            }
+            for (edge, child) in node.children.iter() {
+                if *edge + max_distance >= distance && *edge <= distance + max_distance {
+                    positions.push(*child);
+                }
+            }
+        }
+        close
+    }
+}
+
+/// Did-you-mean engine over the words of the embeddings vocabulary and the terms of the crawled
+/// pages. Unknown words are replaced by the closest known word, nearest edit distance first and
+/// most frequent word among those. The frequency of a word is its share of the embeddings
+/// vocabulary plus its share of the crawled terms, so both sources weigh the same.
+pub struct Speller {
+    vocabulary: RwLock<Vocabulary>,
+}
+
+impl Speller {
+    pub fn new(embeddings: &dyn Embedder, db: &sled::Db) -> sled::Result<Self> {
+        let mut frequencies: HashMap<String, f32> = HashMap::new();
+
+        // Embedding vocabularies are sorted by frequency, so the rank stands in for a count,
+        // as word frequencies fall off with their rank.
+        let ranked: Vec<(String, f32)> = embeddings
+            .vocabulary()
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| word.chars().all(char::is_alphabetic))
+            .map(|(rank, word)| (word.to_lowercase(), 1.0 / (rank as f32 + 1.0)))
+            .collect();
+        let ranked_total: f32 = ranked.iter().map(|(_, weight)| weight).sum();
+        for (word, weight) in ranked {
+            *frequencies.entry(word).or_default() += weight / ranked_total;
+        }
+
+        let statistics = get_all_term_statistics(db)?;
+        let term_count: f32 = statistics
+            .iter()
+            .map(|(_, statistics)| statistics.count as f32)
+            .sum();
+        for (term, statistics) in statistics {
+            if term.chars().all(char::is_alphabetic) {
+                *frequencies.entry(term).or_default() += statistics.count as f32 / term_count;
+            }
+        }
+
+        let mut vocabulary = Vocabulary {
+            frequencies: HashMap::new(),
+            term_count,
+            nodes: Vec::with_capacity(frequencies.len()),
+        };
+        for (word, frequency) in frequencies {
+            vocabulary.add_word(word, frequency);
+        }
+
+        Ok(Self {
+            vocabulary: RwLock::new(vocabulary),
+        })
+    }
+
+    /// Adds the words of a newly crawled `text` to the known words.
+    pub fn add_text(&self, text: &str) {
+        let terms: Vec<String> = tokenize(text)
+            .into_iter()
+            .filter(|term| term.chars().all(char::is_alphabetic))
+            .collect();
+        if terms.is_empty() {
+            return;
+        }
+
+        let mut vocabulary = self.vocabulary.write().unwrap();
+        vocabulary.term_count += terms.len() as f32;
+        let frequency = 1.0 / vocabulary.term_count;
+        for term in terms {
+            vocabulary.add_word(term, frequency);
+        }
+    }
+
+    pub fn is_known(&self, word: &str) -> bool {
+        self.vocabulary
+            .read()
+            .unwrap()
+            .frequencies
+            .contains_key(word)
+    }
+
+    /// Closest known word to `word`, or `None` if it is already known or nothing is close.
+    pub fn correct_word(&self, word: &str) -> Option<String> {
+        let word = word.to_lowercase();
+        if self.is_known(&word) || !word.chars().all(char::is_alphabetic) {
+            return None;
+        }
+
+        let length = word.chars().count();
+        let max_distance = if length <= 4 { 1 } else { 2 };
+
+        let vocabulary = self.vocabulary.read().unwrap();
+        let mut best: Option<(usize, f32, &String)> = None;
+        for (distance, candidate) in vocabulary.get_close_words(&word, max_distance) {
+            let frequency = vocabulary.frequencies[candidate];
+
+            let better = match best {
+                Some((best_distance, best_frequency, _)) => {
+                    distance < best_distance
+                        || (distance == best_distance && frequency > best_frequency)
+                }
+                None => true,
+            };
+            if better {
+                best = Some((distance, frequency, candidate));
+            }
+        }
+
+        best.map(|(_, _, candidate)| candidate.clone())
+    }
+
+    /// `query` with every unknown word replaced by its correction.
+    pub fn correct(&self, query: &str) -> String {
+        query
+            .split_whitespace()
+            .map(|word| self.correct_word(word).unwrap_or(word.to_string()))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// Levenshtein distance between `a` and `b`, or `None` once it exceeds `max_distance`.
+fn get_edit_distance(a: &str, b: &str, max_distance: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + substitution);
+        }
+
+        if current.iter().min().cloned().unwrap_or(0) > max_distance {
+            return None;
+        }
+        previous = current;
+    }
+
+    match previous[b.len()] {
+        distance if distance <= max_distance => Some(distance),
+        _ => None,
+    }
+}
@@ -0,0 +1,486 @@
+use crate::now;
+use crate::pagerank::get_pagerank;
+use rocket::serde::{json, Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Popularity of each phrase, keyed by its normalized form.
+static PHRASES_TREE: &str = "phrases";
+/// Most popular phrases starting with each prefix of up to `RANKED_PREFIX_LENGTH` characters,
+/// best first. A prefix no phrase starts with has no entry.
+static PREFIXES_TREE: &str = "prefixes";
+/// Times each query was searched and when it was last.
+static QUERIES_TREE: &str = "queries";
+/// Title each stored entry adds to the phrases, and the popularity it adds.
+static TITLES_TREE: &str = "titles";
+/// Knowledge base labels already added to the phrases.
+static LABELS_TREE: &str = "labels";
+
+/// Suggestions returned for a prefix at most.
+pub static MAX_SUGGESTIONS: usize = 10;
+/// Longest prefix whose suggestions are kept ranked in advance. Longer prefixes match few enough
+/// phrases to be ranked on each request.
+static RANKED_PREFIX_LENGTH: usize = 3;
+/// Phrases read at most when ranking a prefix, so a short prefix of a large phrases tree
+/// stays cheap to rank.
+static MAX_PREFIX_SCAN: usize = 10000;
+/// Longest phrase worth suggesting, in characters.
+static MAX_PHRASE_LENGTH: usize = 80;
+/// Times a query has to be searched before it is suggested, so queries made once, which may be
+/// private, are never shown to others.
+static MIN_QUERY_COUNT: u64 = 3;
+/// Queries recorded between two prunings of the queries tree.
+static PRUNE_INTERVAL: usize = 1000;
+/// Popularity below which a phrase is dropped.
+static MIN_POPULARITY: f32 = 1e-4;
+
+/// Popularity a phrase gains for each time it was searched, for being the title of a crawled
+/// page, on top of the page's PageRank, and for being a knowledge base label.
+static QUERY_POPULARITY: f32 = 1.0;
+static TITLE_POPULARITY: f32 = 1.0;
+static LABEL_POPULARITY: f32 = 0.5;
+
+/// Lowercases `text` and collapses its whitespace, keeping a trailing space so a prefix ending
+/// in one only matches the following words.
+pub fn normalize_phrase(text: &str) -> String {
+    let mut phrase = text
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase();
+    if !phrase.is_empty() && text.ends_with(char::is_whitespace) {
+        phrase.push(' ');
+    }
+    phrase
+}
+
+fn get_prefix(phrase: &str, length: usize) -> &str {
+    match phrase.char_indices().nth(length) {
+        Some((end, _)) => &phrase[..end],
+        None => phrase,
+    }
+}
+
+fn is_suggestible(phrase: &str) -> bool {
+    !phrase.is_empty() && phrase.chars().count() <= MAX_PHRASE_LENGTH
+}
+
+/// Times a query was searched and when it was last, in seconds since the epoch. Counts stored
+/// before the time was kept read as searched now.
+fn parse_query_count(value: &[u8]) -> (u64, u64) {
+    match value.len() {
+        16 => (
+            u64::from_be_bytes(value[..8].try_into().unwrap()),
+            u64::from_be_bytes(value[8..].try_into().unwrap()),
+        ),
+        _ => (
+            u64::from_be_bytes(value.try_into().unwrap_or([0; 8])),
+            now(),
+        ),
+    }
+}
+
+/// Popularity a query searched `count` times adds to its phrase.
+fn get_query_popularity(count: u64) -> f32 {
+    match count {
+        count if count >= MIN_QUERY_COUNT => count as f32 * QUERY_POPULARITY,
+        _ => 0.0,
+    }
+}
+
+/// Ranked suggestions of a prefix. Only `complete` lists hold every phrase of the prefix that
+/// belongs there, others are the best ones but may miss some after a phrase dropped out, and
+/// are ranked again when next read.
+#[derive(Serialize, Deserialize, Default)]
+struct Ranked {
+    phrases: Vec<String>,
+    complete: bool,
+}
+
+fn sort_ranked(ranked: &mut [(f32, String)]) {
+    ranked.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.1.cmp(&b.1))
+    });
+}
+
+#[derive(Serialize, Deserialize)]
+struct Title {
+    title: String,
+    popularity: f32,
+}
+
+/// Type-ahead over the titles of crawled pages, knowledge base labels and queries searched
+/// often enough, most popular first. Phrases and the ranked suggestions of short prefixes live
+/// on disk, so a lookup is a single read for short prefixes and a range scan for longer ones,
+/// cheap enough for every keystroke.
+pub struct Suggester {
+    phrases: sled::Tree,
+    prefixes: sled::Tree,
+    queries: sled::Tree,
+    titles: sled::Tree,
+    labels: sled::Tree,
+    /// How long a query is remembered after it was last searched.
+    query_ttl: Duration,
+    /// Queries remembered at most, the least searched are forgotten first.
+    max_queries: usize,
+    recorded: AtomicUsize,
+    /// Held while phrases and prefixes are updated, as each update reads them first.
+    writing: Mutex<()>,
+}
+
+impl Suggester {
+    /// Opens the suggestions kept in `db`, forgetting the queries that are too old or too many.
+    pub fn new(db: &sled::Db, query_ttl: Duration, max_queries: usize) -> sled::Result<Self> {
+        let suggester = Self {
+            phrases: db.open_tree(PHRASES_TREE)?,
+            prefixes: db.open_tree(PREFIXES_TREE)?,
+            queries: db.open_tree(QUERIES_TREE)?,
+            titles: db.open_tree(TITLES_TREE)?,
+            labels: db.open_tree(LABELS_TREE)?,
+            query_ttl,
+            max_queries,
+            recorded: AtomicUsize::new(0),
+            writing: Mutex::new(()),
+        };
+        suggester.prune_queries()?;
+        Ok(suggester)
+    }
+
+    fn get_popularity(&self, phrase: &str) -> f32 {
+        match self.phrases.get(phrase) {
+            Ok(Some(value)) => f32::from_be_bytes(value.as_ref().try_into().unwrap_or([0; 4])),
+            _ => 0.0,
+        }
+    }
+
+    /// Popularity of each of `phrases`, best first.
+    fn rank<'a, I>(&self, phrases: I) -> Vec<(f32, String)>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        let mut ranked: Vec<(f32, String)> = phrases
+            .map(|phrase| (self.get_popularity(phrase), phrase.clone()))
+            .collect();
+        sort_ranked(&mut ranked);
+        ranked
+    }
+
+    /// Most popular phrases starting with `prefix`, ranked from the first `MAX_PREFIX_SCAN`
+    /// phrases of the prefix.
+    fn rank_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut ranked: Vec<(f32, String)> = self
+            .phrases
+            .scan_prefix(prefix)
+            .take(MAX_PREFIX_SCAN)
+            .filter_map(|item| item.ok())
+            .map(|(phrase, value)| {
+                (
+                    f32::from_be_bytes(value.as_ref().try_into().unwrap_or([0; 4])),
+                    String::from_utf8_lossy(&phrase).to_string(),
+                )
+            })
+            .collect();
+        sort_ranked(&mut ranked);
+        ranked.truncate(MAX_SUGGESTIONS);
+        ranked.into_iter().map(|(_, phrase)| phrase).collect()
+    }
+
+    /// Ranked suggestions kept for `prefix`. Lists that fail to parse are ranked again.
+    fn get_ranked(&self, prefix: &str) -> Ranked {
+        match self.prefixes.get(prefix) {
+            Ok(Some(value)) => {
+                json::from_str(String::from_utf8_lossy(&value).as_ref()).unwrap_or_default()
+            }
+            _ => Ranked {
+                phrases: vec![],
+                complete: true,
+            },
+        }
+    }
+
+    fn set_ranked(&self, prefix: &str, ranked: &Ranked) -> sled::Result<()> {
+        match ranked.complete && ranked.phrases.is_empty() {
+            true => self.prefixes.remove(prefix)?,
+            false => self
+                .prefixes
+                .insert(prefix, json::to_string(ranked).unwrap().as_str())?,
+        };
+        Ok(())
+    }
+
+    /// Adds `delta` to the popularity of `phrase`, dropping it once nothing makes it popular,
+    /// and moves it along the ranked prefixes it starts with. Phrases missing from a full list
+    /// are no more popular than its last one, so a phrase stays listed as long as it is not
+    /// below that, and otherwise leaves its list to be ranked again when next read.
+    fn add(&self, phrase: &str, delta: f32) -> sled::Result<()> {
+        if !is_suggestible(phrase) || delta == 0.0 {
+            return Ok(());
+        }
+
+        let _writing = self.writing.lock().unwrap();
+        let popularity = self.phrases.update_and_fetch(phrase, |value| {
+            let popularity = match value {
+                Some(value) => f32::from_be_bytes(value.try_into().unwrap_or([0; 4])),
+                None => 0.0,
+            } + delta;
+            match popularity {
+                popularity if popularity < MIN_POPULARITY => None,
+                popularity => Some(popularity.to_be_bytes().to_vec()),
+            }
+        })?;
+        let popularity =
+            popularity.map(|value| f32::from_be_bytes(value.as_ref().try_into().unwrap_or([0; 4])));
+
+        for length in 1..=RANKED_PREFIX_LENGTH.min(phrase.chars().count()) {
+            let prefix = get_prefix(phrase, length);
+            let Ranked { phrases, complete } = self.get_ranked(prefix);
+            let full = phrases.len() >= MAX_SUGGESTIONS;
+            let listed = phrases.iter().any(|known| known == phrase);
+            let mut ranked = self.rank(phrases.iter().filter(|known| *known != phrase));
+
+            // Popularity no phrase missing from the list goes above, `None` when the list holds
+            // every phrase of the prefix.
+            let bound = match (complete && !full, ranked.last()) {
+                (true, _) => None,
+                (false, last) => {
+                    let previous = popularity.unwrap_or(0.0) - delta;
+                    match (last, listed) {
+                        (Some((last, _)), true) => Some(last.min(previous)),
+                        (Some((last, _)), false) => Some(*last),
+                        (None, true) => Some(previous),
+                        (None, false) => Some(f32::INFINITY),
+                    }
+                }
+            };
+            let keep = match (popularity, bound) {
+                (Some(_), None) => true,
+                (Some(popularity), Some(bound)) => popularity >= bound,
+                (None, _) => false,
+            };
+
+            if !listed && !keep {
+                continue;
+            }
+            if keep {
+                ranked.push((popularity.unwrap_or(0.0), phrase.to_string()));
+                sort_ranked(&mut ranked);
+                ranked.truncate(MAX_SUGGESTIONS);
+            }
+            let phrases: Vec<String> = ranked.into_iter().map(|(_, phrase)| phrase).collect();
+            let complete = match keep {
+                true => complete || phrases.len() >= MAX_SUGGESTIONS,
+                false => complete && !full,
+            };
+            self.set_ranked(prefix, &Ranked { phrases, complete })?;
+        }
+        Ok(())
+    }
+
+    /// Ranked suggestions of a short `prefix`, ranking them again first if phrases dropped out
+    /// of the list.
+    fn get_complete_ranked(&self, prefix: &str) -> Vec<String> {
+        let ranked = self.get_ranked(prefix);
+        if ranked.complete {
+            return ranked.phrases;
+        }
+
+        let _writing = self.writing.lock().unwrap();
+        let ranked = Ranked {
+            phrases: self.rank_prefix(prefix),
+            complete: true,
+        };
+        if let Err(e) = self.set_ranked(prefix, &ranked) {
+            println!("Error: {:?}. Error storing ranked suggestions.", e);
+        }
+        ranked.phrases
+    }
+
+    /// Most popular phrases starting with `prefix`, at most `limit` of them.
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let prefix = normalize_phrase(prefix);
+        if prefix.is_empty() {
+            return vec![];
+        }
+
+        let mut phrases = match prefix.chars().count() {
+            length if length <= RANKED_PREFIX_LENGTH => self.get_complete_ranked(&prefix),
+            _ => self.rank_prefix(&prefix),
+        };
+        phrases.truncate(limit);
+        phrases
+    }
+
+    /// Counts `query` as searched once more. Queries are only suggested once searched
+    /// `MIN_QUERY_COUNT` times.
+    pub fn record_query(&self, query: &str) -> sled::Result<()> {
+        let query = normalize_phrase(query);
+        let query = query.trim_end();
+        if !is_suggestible(query) {
+            return Ok(());
+        }
+
+        let searched_at = now();
+        let count = self.queries.update_and_fetch(query, |value| {
+            let count = match value {
+                Some(value) => parse_query_count(value).0,
+                None => 0,
+            };
+            let mut value = (count + 1).to_be_bytes().to_vec();
+            value.extend_from_slice(&searched_at.to_be_bytes());
+            Some(value)
+        })?;
+        if let Some(value) = count {
+            let count = parse_query_count(&value).0;
+            self.add(
+                query,
+                get_query_popularity(count) - get_query_popularity(count - 1),
+            )?;
+        }
+
+        if self.recorded.fetch_add(1, atomic::Ordering::Relaxed) % PRUNE_INTERVAL
+            == PRUNE_INTERVAL - 1
+        {
+            self.prune_queries()?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the queries not searched within `query_ttl`, then the least searched ones past
+    /// `max_queries`.
+    fn prune_queries(&self) -> sled::Result<()> {
+        let expired_before = now().saturating_sub(self.query_ttl.as_secs());
+        let mut kept: Vec<(u64, String)> = vec![];
+        for query in self.queries.iter() {
+            let (query, value) = query?;
+            let query = String::from_utf8_lossy(&query).to_string();
+            let (count, searched_at) = parse_query_count(&value);
+            match searched_at < expired_before {
+                true => self.forget_query(&query, count)?,
+                false => kept.push((count, query)),
+            }
+        }
+
+        if kept.len() > self.max_queries {
+            kept.sort_by(|a, b| b.0.cmp(&a.0));
+            for (count, query) in kept.drain(self.max_queries..) {
+                self.forget_query(&query, count)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn forget_query(&self, query: &str, count: u64) -> sled::Result<()> {
+        self.queries.remove(query)?;
+        self.add(query, -get_query_popularity(count))
+    }
+
+    /// Moves over the queries recorded in `url_db` before suggestions had a database of their
+    /// own.
+    pub fn move_queries(&self, url_db: &sled::Db) -> sled::Result<()> {
+        if !url_db
+            .tree_names()
+            .iter()
+            .any(|name| name.as_ref() == QUERIES_TREE.as_bytes())
+        {
+            return Ok(());
+        }
+
+        for query in url_db.open_tree(QUERIES_TREE)?.iter() {
+            let (query, value) = query?;
+            let (count, searched_at) = parse_query_count(&value);
+            let mut value = count.to_be_bytes().to_vec();
+            value.extend_from_slice(&searched_at.to_be_bytes());
+            if self.queries.insert(&query, value)?.is_none() {
+                self.add(
+                    String::from_utf8_lossy(&query).as_ref(),
+                    get_query_popularity(count),
+                )?;
+            }
+        }
+        url_db.drop_tree(QUERIES_TREE)?;
+        Ok(())
+    }
+
+    /// Suggests the title of the entry stored under `id`, replacing the title it had before.
+    /// Its popularity includes the PageRank the entry has in `url_db`.
+    pub fn add_title(&self, url_db: &sled::Db, id: u128, title: &str) -> sled::Result<()> {
+        let title = Title {
+            title: normalize_phrase(title).trim_end().to_string(),
+            popularity: TITLE_POPULARITY + get_pagerank(url_db, id),
+        };
+        let previous = self
+            .titles
+            .insert(id.to_string(), json::to_string(&title).unwrap().as_str())?
+            .and_then(|value| {
+                json::from_str::<Title>(String::from_utf8_lossy(&value).as_ref()).ok()
+            });
+
+        match previous {
+            Some(previous) if previous.title == title.title => {
+                self.add(&title.title, title.popularity - previous.popularity)
+            }
+            Some(previous) => {
+                self.add(&previous.title, -previous.popularity)?;
+                self.add(&title.title, title.popularity)
+            }
+            None => self.add(&title.title, title.popularity),
+        }
+    }
+
+    /// Stops suggesting the title of the entry stored under `id`.
+    pub fn remove_title(&self, id: u128) -> sled::Result<()> {
+        match self.titles.remove(id.to_string())? {
+            Some(value) => {
+                match json::from_str::<Title>(String::from_utf8_lossy(&value).as_ref()) {
+                    Ok(title) => self.add(&title.title, -title.popularity),
+                    Err(_) => Ok(()),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Brings the titles up to date with the `titles` of the entries stored in `url_db`, for
+    /// entries stored or removed while the suggester was not running.
+    pub fn sync_titles<I>(&self, url_db: &sled::Db, titles: I) -> sled::Result<()>
+    where
+        I: Iterator<Item = (u128, String)>,
+    {
+        let mut stored: HashSet<u128> = HashSet::new();
+        for (id, title) in titles {
+            self.add_title(url_db, id, &title)?;
+            stored.insert(id);
+        }
+
+        for key in self.titles.iter().keys() {
+            let id: u128 = match String::from_utf8_lossy(&key?).parse() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            if !stored.contains(&id) {
+                self.remove_title(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Suggests the knowledge base `labels` not added before.
+    pub fn add_labels<I>(&self, labels: I) -> sled::Result<()>
+    where
+        I: Iterator<Item = String>,
+    {
+        for label in labels {
+            let label = normalize_phrase(&label);
+            let label = label.trim_end();
+            if is_suggestible(label) && self.labels.insert(label, Vec::new())?.is_none() {
+                self.add(label, LABEL_POPULARITY)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use crate::lexical::{get_corpus_statistics, get_term_statistics};
+use ndarray::{Array, Ix1};
+use rocket::serde::json;
+use std::env::var;
+use std::str::FromStr;
+
+static WEIGHTING_TREE: &str = "weighting";
+static COMMON_COMPONENT_KEY: &str = "common_component";
+
+/// How the word vectors of a sentence are averaged into its embedding.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WeightingMode {
+    /// Plain mean over the words of the sentence.
+    Mean,
+    /// Words weighted by their inverse document frequency in the crawled corpus.
+    Idf,
+    /// Smooth inverse frequency, `a / (a + p(word))`, with the corpus common component removed.
+    Sif,
+}
+
+impl FromStr for WeightingMode {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "mean" => Ok(Self::Mean),
+            "idf" => Ok(Self::Idf),
+            "sif" => Ok(Self::Sif),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Word weights taken from the term statistics of the lexical index in the url database.
+pub struct Weighting {
+    pub mode: WeightingMode,
+    db: Option<sled::Db>,
+    sif_a: f32,
+    common_component: Option<Array<f32, Ix1>>,
+}
+
+impl Weighting {
+    pub fn mean() -> Self {
+        Self {
+            mode: WeightingMode::Mean,
+            db: None,
+            sif_a: 0.0,
+            common_component: None,
+        }
+    }
+
+    pub fn load(db: &sled::Db, mode: WeightingMode, sif_a: f32) -> sled::Result<Self> {
+        Ok(Self {
+            mode,
+            db: Some(db.clone()),
+            sif_a,
+            common_component: match mode {
+                WeightingMode::Sif => load_common_component(db)?,
+                _ => None,
+            },
+        })
+    }
+
+    /// Same weighting without the common component, as used while computing it.
+    pub fn without_common_component(&self) -> Self {
+        Self {
+            mode: self.mode,
+            db: self.db.clone(),
+            sif_a: self.sif_a,
+            common_component: None,
+        }
+    }
+
+    pub fn word_weight(&self, word: &str) -> f32 {
+        let db = match (&self.mode, &self.db) {
+            (WeightingMode::Mean, _) | (_, None) => return 1.0,
+            (_, Some(db)) => db,
+        };
+
+        let term = word
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        let (documents, terms) = get_corpus_statistics(db).unwrap_or((0, 0));
+        let statistics = match get_term_statistics(db, &term) {
+            Ok(statistics) => statistics,
+            Err(_) => return 1.0,
+        };
+
+        match self.mode {
+            WeightingMode::Idf => {
+                ((1.0 + documents as f32) / (1.0 + statistics.document_frequency as f32)).ln() + 1.0
+            }
+            WeightingMode::Sif if terms > 0 => {
+                self.sif_a / (self.sif_a + statistics.count as f32 / terms as f32)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Projects the corpus common component out of a sentence embedding.
+    pub fn remove_common_component(&self, vector: Array<f32, Ix1>) -> Array<f32, Ix1> {
+        match &self.common_component {
+            Some(component) if component.len() == vector.len() => {
+                let projection = component.dot(&vector);
+                vector - component * projection
+            }
+            _ => vector,
+        }
+    }
+}
+
+/// Reads `EMBEDDING_WEIGHTING` (`mean`, `idf` or `sif`) and `SIF_A`, defaulting to the mean.
+pub fn load_weighting_from_env(db: &sled::Db) -> sled::Result<Weighting> {
+    let mode = match var("EMBEDDING_WEIGHTING") {
+        Ok(name) => name.parse().unwrap_or(WeightingMode::Mean),
+        Err(_) => WeightingMode::Mean,
+    };
+    let sif_a = match var("SIF_A") {
+        Ok(number) => number.parse().unwrap_or(0.001),
+        Err(_) => 0.001,
+    };
+
+    Weighting::load(db, mode, sif_a)
+}
+
+fn load_common_component(db: &sled::Db) -> sled::Result<Option<Array<f32, Ix1>>> {
+    let weighting = db.open_tree(WEIGHTING_TREE)?;
+    Ok(match weighting.get(COMMON_COMPONENT_KEY)? {
+        Some(value) => json::from_str::<Vec<f32>>(String::from_utf8_lossy(&value).as_ref())
+            .ok()
+            .map(Array::from),
+        None => None,
+    })
+}
+
+pub fn store_common_component(db: &sled::Db, component: &Array<f32, Ix1>) -> sled::Result<()> {
+    let weighting = db.open_tree(WEIGHTING_TREE)?;
+    weighting.insert(
+        COMMON_COMPONENT_KEY,
+        json::to_string(&component.to_vec()).unwrap().as_str(),
+    )?;
+    Ok(())
+}
+
+/// First principal component of `vectors`, found with power iteration.
+pub fn compute_common_component(vectors: &[Array<f32, Ix1>]) -> Option<Array<f32, Ix1>> {
+    let dimension = vectors.first()?.len();
+    let mut component = Array::<f32, Ix1>::ones(dimension) / (dimension as f32).sqrt();
+
+    for _ in 0..50 {
+        let mut next = Array::<f32, Ix1>::zeros(dimension);
+        for vector in vectors {
+            next = next + vector * vector.dot(&component);
+        }
+
+        let norm = next.dot(&next).sqrt();
+        if norm == 0.0 {
+            return None;
+        }
+        component = next / norm;
+    }
+
+    Some(component)
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use tree::dbpedia::{load_dump, DumpKnowledgeBase, KnowledgeBase};
+
+static RESOURCE: &str = "http://dbpedia.org/resource/";
+static RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+fn get_fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn get_resource(name: &str) -> String {
+    format!("{}{}", RESOURCE, name)
+}
+
+fn load_fixture(name: &str) -> DumpKnowledgeBase {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    load_dump(&db, &get_fixture_path(name), "en").unwrap();
+    DumpKnowledgeBase::new(&db, "en").unwrap()
+}
+
+#[test]
+fn loads_the_triples_of_the_dump_language() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    // The Spanish label and summary are skipped.
+    assert_eq!(
+        load_dump(&db, &get_fixture_path("dbpedia.nt"), "en").unwrap(),
+        11
+    );
+    assert_eq!(
+        load_dump(&db, &get_fixture_path("dbpedia.ttl"), "en").unwrap(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn looks_up_labels_and_redirects() {
+    let knowledge_base = load_fixture("dbpedia.nt");
+    let rust = get_resource("Rust_(programming_language)");
+
+    assert_eq!(
+        knowledge_base
+            .get_resource("RUST (Programming Language)")
+            .await
+            .unwrap(),
+        Some(rust.clone())
+    );
+    assert_eq!(
+        knowledge_base.get_resource("rustlang").await.unwrap(),
+        Some(rust.clone())
+    );
+    assert_eq!(
+        knowledge_base
+            .get_resource("rust (lenguaje de programación)")
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        knowledge_base.get_labels().collect::<Vec<String>>(),
+        vec!["iron", "rust", "rust (programming language)", "rustlang"]
+    );
+}
+
+#[tokio::test]
+async fn lists_the_pages_of_disambiguations() {
+    let knowledge_base = load_fixture("dbpedia.nt");
+
+    assert_eq!(
+        knowledge_base.get_candidates("Rust").await.unwrap(),
+        vec![
+            get_resource("Rust_(fungus)"),
+            get_resource("Rust_(programming_language)")
+        ]
+    );
+    assert_eq!(
+        knowledge_base.get_candidates("iron").await.unwrap(),
+        vec![get_resource("Iron")]
+    );
+    assert!(knowledge_base
+        .get_candidates("steel")
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn reads_summaries_and_facts() {
+    let knowledge_base = load_fixture("dbpedia.nt");
+    let rust = get_resource("Rust_(programming_language)");
+
+    // Short abstracts win over long ones, and escapes are unescaped.
+    let summary = Some(String::from(
+        "Rust is a \"multi-paradigm\" language,\u{a0}fast.",
+    ));
+    assert_eq!(knowledge_base.get_summary(&rust).await.unwrap(), summary);
+    assert_eq!(
+        knowledge_base
+            .get_summary(&get_resource("RustLang"))
+            .await
+            .unwrap(),
+        summary
+    );
+    assert_eq!(
+        knowledge_base
+            .get_summary(&get_resource("Iron"))
+            .await
+            .unwrap(),
+        Some(String::from("Iron is a metal."))
+    );
+
+    assert_eq!(
+        knowledge_base
+            .get_facts(&rust, &[RDF_TYPE.to_string()])
+            .await
+            .unwrap(),
+        vec![(
+            RDF_TYPE.to_string(),
+            String::from("http://dbpedia.org/ontology/ProgrammingLanguage")
+        )]
+    );
+}
+
+#[tokio::test]
+async fn reads_prefixed_turtle() {
+    let knowledge_base = load_fixture("dbpedia.ttl");
+    let tree = get_resource("Tree");
+
+    assert_eq!(
+        knowledge_base.get_resource("tree").await.unwrap(),
+        Some(tree.clone())
+    );
+    assert_eq!(
+        knowledge_base.get_summary(&tree).await.unwrap(),
+        Some(String::from("A perennial plant."))
+    );
+}
@@ -0,0 +1,114 @@
+use tree::lexical::{
+    get_corpus_statistics, get_term_statistics, index_document, remove_document, search_bm25,
+};
+use tree::CrawledEntry;
+
+fn open_temporary() -> sled::Db {
+    sled::Config::new().temporary(true).open().unwrap()
+}
+
+fn get_entry(url: &str, body: &str) -> CrawledEntry {
+    CrawledEntry {
+        url: url.to_string(),
+        title: String::new(),
+        header: String::new(),
+        description: String::new(),
+        vec: vec![],
+        language: String::from("en"),
+        body: body.to_string(),
+        passages: vec![],
+        links: vec![],
+        anchors: vec![],
+        fetched_at: 0,
+        etag: None,
+        last_modified: None,
+        recrawl_interval: 0,
+    }
+}
+
+fn get_statistics(db: &sled::Db, term: &str) -> (u64, u64) {
+    let statistics = get_term_statistics(db, term).unwrap();
+    (statistics.document_frequency, statistics.count)
+}
+
+#[test]
+fn updates_term_statistics_on_reindex_and_removal() {
+    let db = open_temporary();
+
+    index_document(
+        &db,
+        1,
+        &get_entry("https://example.org/1", "rust rust tree"),
+    )
+    .unwrap();
+    assert_eq!(get_statistics(&db, "rust"), (1, 2));
+    assert_eq!(get_statistics(&db, "tree"), (1, 1));
+    assert_eq!(get_corpus_statistics(&db).unwrap(), (1, 3));
+
+    index_document(&db, 1, &get_entry("https://example.org/1", "Rust forest")).unwrap();
+    assert_eq!(get_statistics(&db, "rust"), (1, 1));
+    assert_eq!(get_statistics(&db, "tree"), (0, 0));
+    assert_eq!(get_statistics(&db, "forest"), (1, 1));
+    assert_eq!(get_corpus_statistics(&db).unwrap(), (1, 2));
+    assert!(search_bm25(&db, "tree", 10).unwrap().is_empty());
+
+    index_document(&db, 2, &get_entry("https://example.org/2", "rust")).unwrap();
+    assert_eq!(get_statistics(&db, "rust"), (2, 2));
+    assert_eq!(get_corpus_statistics(&db).unwrap(), (2, 3));
+
+    remove_document(&db, 1).unwrap();
+    assert_eq!(get_statistics(&db, "rust"), (1, 1));
+    assert_eq!(get_statistics(&db, "forest"), (0, 0));
+    assert_eq!(get_corpus_statistics(&db).unwrap(), (1, 1));
+
+    // Removing a document that is not indexed leaves the statistics alone.
+    remove_document(&db, 1).unwrap();
+    assert_eq!(get_corpus_statistics(&db).unwrap(), (1, 1));
+    assert_eq!(
+        search_bm25(&db, "rust forest", 10)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<u128>>(),
+        vec![2]
+    );
+}
+
+#[test]
+fn ranks_documents_with_bm25() {
+    let db = open_temporary();
+    let documents = [
+        (1, "rust rust rust programming"),
+        (
+            2,
+            "rust programming language guide for beginners and experts alike",
+        ),
+        (3, "tree search engine"),
+    ];
+    for (id, body) in documents {
+        index_document(
+            &db,
+            id,
+            &get_entry(&format!("https://example.org/{}", id), body),
+        )
+        .unwrap();
+    }
+
+    let get_ids = |query: &str, limit: usize| -> Vec<u128> {
+        search_bm25(&db, query, limit)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    };
+
+    // More occurrences in a shorter document rank higher.
+    assert_eq!(get_ids("rust", 10), vec![1, 2]);
+    // A rare term outweighs a common one.
+    assert_eq!(get_ids("rust engine", 10), vec![3, 1, 2]);
+    assert_eq!(get_ids("RUST, engine!", 2), vec![3, 1]);
+    assert!(get_ids("python", 10).is_empty());
+
+    let scores = search_bm25(&db, "rust engine", 10).unwrap();
+    assert!(scores.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+}
@@ -0,0 +1,71 @@
+use tree::pagerank::{compute_pagerank, get_pagerank, remove_links, store_links};
+
+fn open_temporary() -> sled::Db {
+    sled::Config::new().temporary(true).open().unwrap()
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 1e-3,
+        "expected {}, got {}",
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn ranks_the_most_linked_page_first() {
+    let db = open_temporary();
+    store_links(&db, 1, &[2]).unwrap();
+    store_links(&db, 2, &[1]).unwrap();
+    store_links(&db, 3, &[1]).unwrap();
+    store_links(&db, 4, &[1, 4]).unwrap();
+
+    assert_eq!(compute_pagerank(&db, 0.85, 50).unwrap(), 4);
+    assert_close(get_pagerank(&db, 1), 1.0);
+    assert_close(get_pagerank(&db, 2), 0.9284);
+    assert_close(get_pagerank(&db, 3), 0.0782);
+    // Links to the page itself are not counted.
+    assert_close(get_pagerank(&db, 3), get_pagerank(&db, 4));
+    assert_eq!(get_pagerank(&db, 5), 0.0);
+}
+
+#[test]
+fn spreads_the_rank_of_dangling_pages() {
+    let db = open_temporary();
+    // Page 2 is only linked to, so its rank goes to every page.
+    store_links(&db, 1, &[2]).unwrap();
+
+    assert_eq!(compute_pagerank(&db, 0.85, 50).unwrap(), 2);
+    // Ranks r1 = 0.15 / 2 + 0.85 * r2 / 2 and r2 = 1 - r1 give r1 / r2 = 20 / 37.
+    assert_close(get_pagerank(&db, 2), 1.0);
+    assert_close(get_pagerank(&db, 1), 20.0 / 37.0);
+
+    remove_links(&db, 1).unwrap();
+    assert_eq!(compute_pagerank(&db, 0.85, 50).unwrap(), 0);
+}
+
+#[test]
+fn converges_to_the_stationary_ranks() {
+    let db = open_temporary();
+    store_links(&db, 1, &[2, 3]).unwrap();
+    store_links(&db, 2, &[3]).unwrap();
+    store_links(&db, 3, &[1]).unwrap();
+
+    compute_pagerank(&db, 0.85, 100).unwrap();
+    let converged: Vec<f32> = (1..=3).map(|id| get_pagerank(&db, id)).collect();
+    compute_pagerank(&db, 0.85, 200).unwrap();
+    for (id, rank) in (1..=3).zip(converged) {
+        assert!((get_pagerank(&db, id) - rank).abs() < 1e-5);
+    }
+
+    // A cycle ranks every page the same.
+    let db = open_temporary();
+    store_links(&db, 1, &[2]).unwrap();
+    store_links(&db, 2, &[3]).unwrap();
+    store_links(&db, 3, &[1]).unwrap();
+    compute_pagerank(&db, 0.85, 50).unwrap();
+    for id in 1..=3 {
+        assert_close(get_pagerank(&db, id), 1.0);
+    }
+}
@@ -0,0 +1,82 @@
+use reqwest::Url;
+use std::time::Duration;
+use tree::robots::{get_product_token, Robots};
+
+fn is_allowed(robots: &Robots, path: &str) -> bool {
+    robots.is_allowed(&Url::parse(&format!("https://example.org{}", path)).unwrap())
+}
+
+#[test]
+fn matches_wildcards_and_anchors() {
+    let robots = Robots::parse(
+        "User-agent: *\n\
+         Disallow: /private\n\
+         Disallow: /*.pdf$\n\
+         Disallow: /search*q=\n\
+         Allow: /private/open$\n",
+        "tree/0.1",
+    );
+
+    assert!(is_allowed(&robots, "/"));
+    assert!(!is_allowed(&robots, "/private"));
+    assert!(!is_allowed(&robots, "/private/page"));
+    assert!(is_allowed(&robots, "/private/open"));
+    assert!(!is_allowed(&robots, "/private/open/more"));
+    assert!(!is_allowed(&robots, "/files/report.pdf"));
+    assert!(is_allowed(&robots, "/files/report.pdf.html"));
+    assert!(!is_allowed(&robots, "/search?lang=en&q=rust"));
+    assert!(is_allowed(&robots, "/search?lang=en"));
+}
+
+#[test]
+fn prefers_allow_on_ties() {
+    let robots = Robots::parse("User-agent: *\nDisallow: /page\nAllow: /page\n", "tree/0.1");
+
+    assert!(is_allowed(&robots, "/page"));
+}
+
+#[test]
+fn selects_the_group_naming_the_product_token() {
+    let text = "User-agent: *\n\
+                Disallow: /\n\
+                \n\
+                User-agent: other\n\
+                User-agent: Tree\n\
+                Disallow: /private\n\
+                Crawl-delay: 2\n\
+                \n\
+                User-agent: treebot\n\
+                Disallow: /public\n\
+                \n\
+                Sitemap: https://example.org/sitemap.xml\n";
+
+    let robots = Robots::parse(text, "Tree/0.1 (+https://example.org/bot)");
+    assert!(is_allowed(&robots, "/public"));
+    assert!(!is_allowed(&robots, "/private"));
+    assert_eq!(robots.crawl_delay, Some(Duration::from_secs(2)));
+    assert_eq!(robots.sitemaps, vec!["https://example.org/sitemap.xml"]);
+
+    // Agents merely contained in the product token do not pick their group.
+    let robots = Robots::parse(text, "supertree/1.0");
+    assert!(!is_allowed(&robots, "/public"));
+    assert_eq!(robots.crawl_delay, None);
+}
+
+#[test]
+fn reads_product_tokens() {
+    assert_eq!(get_product_token("Tree/0.1 (+https://example.org)"), "tree");
+    assert_eq!(get_product_token("tree"), "tree");
+    assert_eq!(get_product_token(""), "");
+}
+
+#[test]
+fn ignores_rules_past_the_length_limit() {
+    let text = format!(
+        "User-agent: *\nDisallow: /a\n#x{}\nDisallow: /b\n",
+        "é".repeat(300 * 1024)
+    );
+    let robots = Robots::parse(&text, "tree/0.1");
+
+    assert!(!is_allowed(&robots, "/a"));
+    assert!(is_allowed(&robots, "/b"));
+}
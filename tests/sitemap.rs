@@ -0,0 +1,103 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+use tree::sitemap::{parse_lastmod, parse_sitemap, read_sitemap, Sitemap, SitemapEntry};
+
+fn get_urls(entries: &[SitemapEntry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.url.as_str()).collect()
+}
+
+#[test]
+fn parses_lastmod_dates_and_times() {
+    assert_eq!(parse_lastmod("1970-01-01"), Some(0));
+    assert_eq!(parse_lastmod("2022-06-01"), Some(1654041600));
+    assert_eq!(parse_lastmod(" 2022-06 "), Some(1654041600));
+    assert_eq!(parse_lastmod("2022-06-01T08:30:00Z"), Some(1654072200));
+    assert_eq!(parse_lastmod("2022-06-01T10:30:00+02:00"), Some(1654072200));
+    assert_eq!(
+        parse_lastmod("2022-06-01T06:30:00.5-02:00"),
+        Some(1654072200)
+    );
+
+    assert_eq!(parse_lastmod("yesterday"), None);
+    assert_eq!(parse_lastmod("2022-13-01"), None);
+    assert_eq!(parse_lastmod("2022-06-01T10:30:00+02"), None);
+    assert_eq!(parse_lastmod("1969-12-31"), None);
+}
+
+#[test]
+fn reads_gzipped_and_plain_sitemaps() {
+    let text = "https://example.org/\nhttps://example.org/about\n";
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.as_bytes()).unwrap();
+    let gzipped = encoder.finish().unwrap();
+
+    assert_eq!(read_sitemap(&gzipped).unwrap(), text);
+    assert_eq!(read_sitemap(text.as_bytes()).unwrap(), text);
+}
+
+#[test]
+fn tells_sitemap_indexes_from_url_sets() {
+    let index = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+          <sitemap><loc>https://example.org/pages.xml</loc><lastmod>2022-06-01</lastmod></sitemap>
+          <sitemap><loc><![CDATA[https://example.org/posts.xml.gz]]></loc></sitemap>
+        </sitemapindex>"#;
+    match parse_sitemap(index) {
+        Sitemap::Index(sitemaps) => {
+            assert_eq!(
+                get_urls(&sitemaps),
+                vec![
+                    "https://example.org/pages.xml",
+                    "https://example.org/posts.xml.gz"
+                ]
+            );
+            assert_eq!(sitemaps[0].lastmod, Some(1654041600));
+            assert_eq!(sitemaps[1].lastmod, None);
+        }
+        Sitemap::Urls(_) => panic!("sitemap index read as a url set"),
+    }
+
+    let urls = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+          <url>
+            <loc>https://example.org/search?q=tree&amp;page=2</loc>
+            <priority>1.5</priority>
+          </url>
+          <url><loc>https://example.org/about</loc><priority>0.3</priority></url>
+          <url><loc> </loc></url>
+          <url><loc>https://example.org/contact</loc><priority>high</priority></url>
+        </urlset>"#;
+    match parse_sitemap(urls) {
+        Sitemap::Urls(pages) => {
+            assert_eq!(
+                get_urls(&pages),
+                vec![
+                    "https://example.org/search?q=tree&page=2",
+                    "https://example.org/about",
+                    "https://example.org/contact"
+                ]
+            );
+            assert_eq!(pages[0].priority, Some(1.0));
+            assert_eq!(pages[1].priority, Some(0.3));
+            assert_eq!(pages[2].priority, None);
+        }
+        Sitemap::Index(_) => panic!("url set read as a sitemap index"),
+    }
+}
+
+#[test]
+fn reads_text_sitemaps() {
+    match parse_sitemap(
+        "\u{feff}https://example.org/\n  ftp://example.org/file\nhttp://example.org/a \n",
+    ) {
+        Sitemap::Urls(pages) => {
+            assert_eq!(
+                get_urls(&pages),
+                vec!["https://example.org/", "http://example.org/a"]
+            )
+        }
+        Sitemap::Index(_) => panic!("text sitemap read as a sitemap index"),
+    }
+}
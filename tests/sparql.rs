@@ -0,0 +1,155 @@
+use reqwest::Url;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tree::dbpedia::{KnowledgeBase, SparqlKnowledgeBase};
+use tree::sparql::{escape_iri, escape_literal, Select, Term};
+
+/// Serves `body` as the SPARQL results of the first request made to it and sends back the query
+/// that request carried.
+async fn stand_in_endpoint(body: &'static str) -> (String, oneshot::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}/sparql", listener.local_addr().unwrap());
+    let (sender, receiver) = oneshot::channel();
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+
+        let request = String::from_utf8_lossy(&request).to_string();
+        let target = request.split_whitespace().nth(1).unwrap_or("");
+        let query = Url::parse(&format!("http://localhost{}", target))
+            .unwrap()
+            .query_pairs()
+            .find(|(name, _)| name == "query")
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default();
+        sender.send(query).unwrap();
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/sparql-results+json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    (endpoint, receiver)
+}
+
+#[test]
+fn escapes_literals_and_iris() {
+    assert_eq!(
+        escape_literal("O'Reilly \"x\"\n\\"),
+        "O\\'Reilly \\\"x\\\"\\n\\\\"
+    );
+    assert_eq!(escape_iri("http://a/b> }{"), "http://a/b%3E%20%7D%7B");
+
+    let query = Select::new(&["s"])
+        .triple(
+            Term::variable("s"),
+            Term::iri("http://example.org/label"),
+            Term::literal("a' } >", Some("en} .")),
+        )
+        .build();
+    assert_eq!(
+        query,
+        "SELECT ?s WHERE { ?s <http://example.org/label> \"a\\' } >\"@en . }"
+    );
+}
+
+#[tokio::test]
+async fn resolves_resources_from_bindings() {
+    let (endpoint, query) = stand_in_endpoint(
+        r#"{"head":{"vars":["s"]},"results":{"bindings":[{"s":{"type":"uri","value":"http://dbpedia.org/resource/O'Reilly_Media"}}]}}"#,
+    )
+    .await;
+
+    let knowledge_base = SparqlKnowledgeBase::new(reqwest::Client::new(), &endpoint, "en");
+    let resource = knowledge_base.get_resource("O'Reilly }").await.unwrap();
+
+    assert_eq!(
+        resource.as_deref(),
+        Some("http://dbpedia.org/resource/O'Reilly_Media")
+    );
+    assert!(query.await.unwrap().contains("\"O\\'Reilly }\"@en"));
+}
+
+#[tokio::test]
+async fn reports_missing_summaries() {
+    let (endpoint, query) =
+        stand_in_endpoint(r#"{"head":{"vars":["desc"]},"results":{"bindings":[]}}"#).await;
+
+    let knowledge_base = SparqlKnowledgeBase::new(reqwest::Client::new(), &endpoint, "en");
+    let summary = knowledge_base
+        .get_summary("http://dbpedia.org/resource/A>B")
+        .await
+        .unwrap();
+
+    assert_eq!(summary, None);
+    assert!(query
+        .await
+        .unwrap()
+        .contains("<http://dbpedia.org/resource/A%3EB>"));
+}
+
+#[tokio::test]
+async fn ranks_direct_candidates_first() {
+    let (endpoint, query) = stand_in_endpoint(
+        r#"{"head":{"vars":["s"]},"results":{"bindings":[{"s":{"type":"uri","value":"http://dbpedia.org/resource/Mercury_(planet)"}},{"s":{"type":"uri","value":"http://dbpedia.org/resource/Mercury_(element)"}}]}}"#,
+    )
+    .await;
+
+    let knowledge_base = SparqlKnowledgeBase::new(reqwest::Client::new(), &endpoint, "en");
+    let candidates = knowledge_base.get_candidates("mercury").await.unwrap();
+
+    assert_eq!(
+        candidates,
+        vec![
+            "http://dbpedia.org/resource/Mercury_(planet)",
+            "http://dbpedia.org/resource/Mercury_(element)",
+        ]
+    );
+    let query = query.await.unwrap();
+    assert!(query.contains("BIND (0 AS ?rank)"));
+    assert!(query.contains("BIND (2 AS ?rank)"));
+    assert!(query.contains("GROUP BY ?s ORDER BY MIN(?rank) LIMIT 10"));
+}
+
+#[tokio::test]
+async fn looks_up_every_term_in_one_query() {
+    let (endpoint, query) = stand_in_endpoint(
+        r#"{"head":{"vars":["label","s"]},"results":{"bindings":[{"label":{"type":"literal","xml:lang":"en","value":"Rust Belt"},"s":{"type":"uri","value":"http://dbpedia.org/resource/Rust_Belt"}}]}}"#,
+    )
+    .await;
+
+    let knowledge_base = SparqlKnowledgeBase::new(reqwest::Client::new(), &endpoint, "en");
+    let resources = knowledge_base
+        .get_resources(&[String::from("rust belt"), String::from("ohio")])
+        .await
+        .unwrap();
+
+    assert_eq!(resources.len(), 1);
+    assert_eq!(
+        resources.get("rust belt").map(String::as_str),
+        Some("http://dbpedia.org/resource/Rust_Belt")
+    );
+    let query = query.await.unwrap();
+    assert!(query.contains("SELECT ?label ?s WHERE"));
+    for label in [
+        "\"rust belt\"@en",
+        "\"Rust Belt\"@en",
+        "\"ohio\"@en",
+        "\"Ohio\"@en",
+    ] {
+        assert!(query.contains(label));
+    }
+}
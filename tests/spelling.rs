@@ -0,0 +1,89 @@
+use finalfusion::compat::text::ReadText;
+use finalfusion::embeddings::Embeddings;
+use finalfusion::storage::NdArray;
+use finalfusion::vocab::SimpleVocab;
+use std::io::Cursor;
+use tree::lexical::index_document;
+use tree::spelling::Speller;
+use tree::CrawledEntry;
+
+fn open_temporary() -> sled::Db {
+    sled::Config::new().temporary(true).open().unwrap()
+}
+
+/// Embeddings of `words`, most frequent first, with made up vectors.
+fn get_embeddings(words: &[&str]) -> Embeddings<SimpleVocab, NdArray> {
+    let text: String = words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| format!("{} {} 1.0\n", word, index))
+        .collect();
+    Embeddings::read_text(&mut Cursor::new(text)).unwrap()
+}
+
+fn get_entry(body: &str) -> CrawledEntry {
+    CrawledEntry {
+        url: String::from("https://example.org/"),
+        title: String::new(),
+        header: String::new(),
+        description: String::new(),
+        vec: vec![],
+        language: String::from("en"),
+        body: body.to_string(),
+        passages: vec![],
+        links: vec![],
+        anchors: vec![],
+        fetched_at: 0,
+        etag: None,
+        last_modified: None,
+        recrawl_interval: 0,
+    }
+}
+
+#[test]
+fn corrects_unknown_words_to_the_closest_known_one() {
+    let embeddings = get_embeddings(&[
+        "the", "book", "brook", "books", "booking", "cook", "look", "search", "engine", "tree",
+    ]);
+    let speller = Speller::new(&embeddings, &open_temporary()).unwrap();
+
+    assert_eq!(speller.correct("serch Engin"), "search engine");
+    // `book` and `brook` are both one edit away, and `book` is the more frequent.
+    assert_eq!(speller.correct_word("boook"), Some(String::from("book")));
+    assert_eq!(
+        speller.correct_word("bookings"),
+        Some(String::from("booking"))
+    );
+    assert_eq!(speller.correct_word("tree"), None);
+    assert_eq!(speller.correct_word("h3llo"), None);
+    // Short words are corrected by one edit at most.
+    assert_eq!(speller.correct_word("tx"), None);
+    assert_eq!(speller.correct("xyzzy tree"), "xyzzy tree");
+}
+
+#[test]
+fn weighs_crawled_terms_like_the_embeddings_vocabulary() {
+    let embeddings = get_embeddings(&["house", "horse", "the", "and"]);
+
+    let speller = Speller::new(&embeddings, &open_temporary()).unwrap();
+    assert_eq!(speller.correct_word("hourse"), Some(String::from("house")));
+
+    // Crawled pages that mostly mention horses make `horse` the more frequent word.
+    let db = open_temporary();
+    index_document(&db, 1, &get_entry("horse horse horse house")).unwrap();
+    let speller = Speller::new(&embeddings, &db).unwrap();
+    assert_eq!(speller.correct_word("hourse"), Some(String::from("horse")));
+}
+
+#[test]
+fn learns_words_of_added_text() {
+    let embeddings = get_embeddings(&["the", "tree"]);
+    let speller = Speller::new(&embeddings, &open_temporary()).unwrap();
+    assert!(!speller.is_known("zebra"));
+    assert_eq!(speller.correct_word("zebre"), None);
+
+    speller.add_text("Zebras and a zebra, 42 of them");
+    assert!(speller.is_known("zebra"));
+    assert!(!speller.is_known("42"));
+    assert_eq!(speller.correct_word("zebre"), Some(String::from("zebra")));
+}
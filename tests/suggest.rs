@@ -0,0 +1,100 @@
+use std::time::Duration;
+use tree::suggest::Suggester;
+
+fn open_temporary() -> sled::Db {
+    sled::Config::new().temporary(true).open().unwrap()
+}
+
+#[test]
+fn ranks_prefixes_by_popularity() {
+    let url_db = open_temporary();
+    let suggester = Suggester::new(&open_temporary(), Duration::from_secs(3600), 100).unwrap();
+
+    suggester
+        .add_labels(vec![String::from("Rust Belt")].into_iter())
+        .unwrap();
+    suggester
+        .add_title(&url_db, 1, "Rust  Programming Language")
+        .unwrap();
+
+    assert_eq!(
+        suggester.suggest("ru", 10),
+        vec!["rust programming language", "rust belt"]
+    );
+    assert_eq!(
+        suggester.suggest("Rust P", 10),
+        vec!["rust programming language"]
+    );
+    assert_eq!(suggester.suggest("rust b", 1), vec!["rust belt"]);
+    assert!(suggester.suggest("x", 10).is_empty());
+}
+
+#[test]
+fn suggests_queries_once_searched_often_enough() {
+    let url_db = open_temporary();
+    let suggester = Suggester::new(&open_temporary(), Duration::from_secs(3600), 100).unwrap();
+    suggester.add_title(&url_db, 1, "Rust Belt").unwrap();
+
+    suggester.record_query("rust tutorial").unwrap();
+    suggester.record_query("rust tutorial").unwrap();
+    assert_eq!(suggester.suggest("rus", 10), vec!["rust belt"]);
+
+    suggester.record_query("Rust  tutorial ").unwrap();
+    assert_eq!(
+        suggester.suggest("rus", 10),
+        vec!["rust tutorial", "rust belt"]
+    );
+}
+
+#[test]
+fn replaces_and_removes_titles() {
+    let url_db = open_temporary();
+    let suggester = Suggester::new(&open_temporary(), Duration::from_secs(3600), 100).unwrap();
+
+    suggester.add_title(&url_db, 1, "Rust Belt").unwrap();
+    suggester.add_title(&url_db, 2, "Rust Belt").unwrap();
+    suggester.add_title(&url_db, 3, "Rust Language").unwrap();
+    assert_eq!(
+        suggester.suggest("r", 10),
+        vec!["rust belt", "rust language"]
+    );
+
+    suggester.add_title(&url_db, 1, "Rusty Nail").unwrap();
+    suggester.remove_title(2).unwrap();
+    assert_eq!(
+        suggester.suggest("r", 10),
+        vec!["rust language", "rusty nail"]
+    );
+
+    suggester
+        .sync_titles(
+            &url_db,
+            vec![(3, String::from("Rust Language"))].into_iter(),
+        )
+        .unwrap();
+    assert_eq!(suggester.suggest("r", 10), vec!["rust language"]);
+}
+
+#[test]
+fn ranks_a_prefix_again_once_a_phrase_drops_out() {
+    let url_db = open_temporary();
+    let suggester = Suggester::new(&open_temporary(), Duration::from_secs(3600), 100).unwrap();
+
+    for id in 0..10 {
+        suggester
+            .add_title(&url_db, id, &format!("a{}", id))
+            .unwrap();
+    }
+    suggester.add_title(&url_db, 10, "a9").unwrap();
+    suggester
+        .add_labels(vec![String::from("al")].into_iter())
+        .unwrap();
+    let mut expected: Vec<String> = vec![String::from("a9")];
+    expected.extend((0..9).map(|id| format!("a{}", id)));
+    assert_eq!(suggester.suggest("a", 10), expected);
+
+    suggester.remove_title(0).unwrap();
+    expected.remove(1);
+    expected.push(String::from("al"));
+    assert_eq!(suggester.suggest("a", 10), expected);
+}